@@ -1,15 +1,107 @@
-use std::{fmt::Display, format, fs, println, thread, time::SystemTime};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    fmt::{Display, Write},
+    format, fs,
+    ops::Range,
+    path::PathBuf,
+    println, process,
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
 use chrono::{DateTime, Local};
+use clap::Parser;
 use fontdue::Font;
 use hidapi::{DeviceInfo, HidApi, HidDevice, HidError};
 use itertools::Itertools;
-use sysinfo::{CpuExt, System, SystemExt};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use sysinfo::{ComponentExt, CpuExt, DiskExt, NetworkExt, System, SystemExt};
 
+#[cfg(test)]
+mod testing;
+
+/// Size of every write to the stock firmware, see `Protocol`
 pub const PAYLOAD_SIZE: usize = 32;
 
-fn is_my_device(device: &DeviceInfo) -> bool {
-    device.vendor_id() == 0x4B42 && device.product_id() == 0x6072 && device.usage_page() == 0xFF60
+/// Packet indices are a single byte, which caps how large a framebuffer can be sent
+pub const MAX_PACKETS: usize = u8::MAX as usize + 1;
+
+/// 12 hour clock without seconds, e.g. `09:41 PM`
+pub const DEFAULT_TIME_FORMAT: &str = "%I:%M %p";
+
+/// Delay before the first reconnection attempt, doubled after each failure up to the max
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Identifies which HID interface to talk to. The default matches the stock keyboard firmware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceFilter {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usage_page: u16,
+}
+
+impl Default for DeviceFilter {
+    fn default() -> Self {
+        Self {
+            vendor_id: 0x4B42,
+            product_id: 0x6072,
+            usage_page: 0xFF60,
+        }
+    }
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        device.vendor_id() == self.vendor_id
+            && device.product_id() == self.product_id
+            && device.usage_page() == self.usage_page
+    }
+}
+
+/// A `HidAdapter` that discards every write, for composing frames without a keyboard
+pub struct NullAdapter;
+
+impl HidAdapter for NullAdapter {
+    fn write(&self, data: &[u8]) -> Result<usize, HidError> {
+        Ok(data.len())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// Every connected device matching `filter`, in enumeration order
+pub fn find_devices(api: &HidApi, filter: &DeviceFilter) -> Vec<DeviceInfo> {
+    api.device_list()
+        .filter(|device| filter.matches(device))
+        .cloned()
+        .collect()
+}
+
+/// The first connected device matching `filter`
+pub fn find_device(api: &HidApi, filter: &DeviceFilter) -> Option<DeviceInfo> {
+    find_devices(api, filter).into_iter().next()
+}
+
+/// The matching device with the given serial number, for picking one of several keyboards
+pub fn find_device_by_serial(
+    api: &HidApi,
+    filter: &DeviceFilter,
+    serial: &str,
+) -> Option<DeviceInfo> {
+    find_devices(api, filter)
+        .into_iter()
+        .find(|device| device.serial_number() == Some(serial))
 }
 
 pub trait HidAdapter {
@@ -28,29 +120,199 @@ impl HidAdapter for HidDevice {
     }
 }
 
-#[derive(PartialEq, Clone)]
-pub struct DataPacket {
-    index: u8,
-    payload: [u8; PAYLOAD_SIZE - 2],
+/// Source of the current time, so time-dependent drawing can be driven deterministically
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
 }
 
-impl DataPacket {
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = vec![1, self.index];
-        bytes.extend_from_slice(&self.payload);
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A `Clock` that only moves when told to
+pub struct MockClock {
+    now: std::cell::Cell<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: std::cell::Cell::new(now),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        self.now.get()
+    }
+
+    /// Returns immediately, moving the clock forward by `duration` instead
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// The clock's current time in a chrono strftime `format`
+fn format_time(clock: &dyn Clock, format: &str) -> Result<String, ScreenError> {
+    let formatted_time: DateTime<Local> = clock.now().into();
+
+    // `to_string` panics on a bad format, writing into a String reports it instead
+    let mut time_string = String::new();
+    write!(time_string, "{}", formatted_time.format(format))
+        .map_err(|_| ScreenError::TimeFormat(format.to_string()))?;
+
+    Ok(time_string)
+}
+
+/// Wire format details that vary between firmware builds. The default matches the stock
+/// keyboard firmware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Protocol {
+    /// Size of every write, report id included
+    pub payload_size: usize,
+    /// Leading byte of `Packet::Data` writes
+    pub data_report_id: u8,
+    /// Leading byte of `Packet::Command` writes
+    pub command_report_id: u8,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self {
+            payload_size: PAYLOAD_SIZE,
+            data_report_id: 1,
+            command_report_id: 2,
+        }
+    }
+}
+
+impl Protocol {
+    /// Framebuffer bytes carried by each `Packet::Data`, after the report id and index
+    pub fn chunk_size(&self) -> usize {
+        self.payload_size - 2
+    }
+}
+
+/// Bytes in a `width` x `height` framebuffer. Each strip of 8 columns takes `height` bytes, so a
+/// width that isn't a multiple of 8 pads the last strip out.
+fn buffer_len(width: usize, height: usize) -> usize {
+    width.div_ceil(8) * height
+}
+
+/// Rejects screens whose framebuffer would need more packets than an index byte can number
+fn check_packet_count(width: usize, height: usize, protocol: &Protocol) -> Result<(), ScreenError> {
+    let packets = buffer_len(width, height).div_ceil(protocol.chunk_size());
+    if packets > MAX_PACKETS {
+        return Err(ScreenError::TooManyPackets {
+            packets,
+            max: MAX_PACKETS,
+        });
+    }
+
+    Ok(())
+}
+
+/// Everything sent to the keyboard. On the wire each packet is `Protocol::payload_size` bytes:
+/// a report id (`Packet::report_id`, 1 or 2 by default) followed by the body, zero padded.
+///
+/// | Report id           | Packet    | Body                                                     |
+/// |---------------------|-----------|----------------------------------------------------------|
+/// | `data_report_id`    | `Data`    | index, then bytes `index * chunk_size()..` of the screen |
+/// | `command_report_id` | `Command` | command id, then its arguments (see `Command`)           |
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Data(DataPacket),
+    Command(Command),
+}
+
+impl Packet {
+    pub fn report_id(&self, protocol: &Protocol) -> u8 {
+        match self {
+            Packet::Data(_) => protocol.data_report_id,
+            Packet::Command(_) => protocol.command_report_id,
+        }
+    }
+
+    pub fn to_bytes(&self, protocol: &Protocol) -> Vec<u8> {
+        let mut bytes = vec![self.report_id(protocol)];
+
+        match self {
+            Packet::Data(packet) => {
+                bytes.push(packet.index);
+                bytes.extend_from_slice(&packet.payload);
+            }
+            Packet::Command(Command::SetContrast(level)) => bytes.extend([0x01, *level]),
+        }
+
+        bytes.resize(protocol.payload_size, 0);
         bytes
     }
 
-    pub fn send(&self, device: &dyn HidAdapter) -> Result<(), HidError> {
-        let bytes = self.to_bytes();
+    pub fn send(&self, device: &dyn HidAdapter, protocol: &Protocol) -> Result<(), HidError> {
+        let bytes = self.to_bytes(protocol);
 
-        // println!("{:?}", bytes)/* ; */
-        device.write(&bytes)?;
+        debug!("sending {bytes:02x?}");
+        let sent = device.write(&bytes)?;
+
+        // A partial write leaves the firmware with a truncated packet, so treat it as a failure.
+        // Some platforms pad to the report size and report more, which is fine.
+        if sent < bytes.len() {
+            return Err(HidError::IncompleteSendError {
+                sent,
+                all: bytes.len(),
+            });
+        }
 
         Ok(())
     }
+}
+
+impl From<DataPacket> for Packet {
+    fn from(packet: DataPacket) -> Self {
+        Packet::Data(packet)
+    }
+}
+
+impl From<Command> for Packet {
+    fn from(command: Command) -> Self {
+        Packet::Command(command)
+    }
+}
+
+/// A slice of the framebuffer, sent as `Packet::Data`
+#[derive(Debug, PartialEq, Clone)]
+pub struct DataPacket {
+    index: u8,
+    payload: Vec<u8>,
+}
+
+impl DataPacket {
+    pub fn to_bytes(&self, protocol: &Protocol) -> Vec<u8> {
+        Packet::Data(self.clone()).to_bytes(protocol)
+    }
+
+    pub fn send(&self, device: &dyn HidAdapter, protocol: &Protocol) -> Result<(), HidError> {
+        Packet::Data(self.clone()).send(device, protocol)
+    }
 
-    pub fn new(starting_index: u8, payload: [u8; PAYLOAD_SIZE - 2]) -> Self {
+    pub fn new(starting_index: u8, payload: Vec<u8>) -> Self {
         Self {
             index: starting_index,
             payload,
@@ -58,25 +320,231 @@ impl DataPacket {
     }
 }
 
-struct Screen {
+/// A display setting for the firmware rather than pixel data, sent as `Packet::Command`
+///
+/// | Command       | id   | arguments                      |
+/// |---------------|------|--------------------------------|
+/// | `SetContrast` | 0x01 | level, 0 (dimmest) to 255      |
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    SetContrast(u8),
+}
+
+impl Command {
+    pub fn to_bytes(&self, protocol: &Protocol) -> Vec<u8> {
+        Packet::Command(*self).to_bytes(protocol)
+    }
+
+    pub fn send(&self, device: &dyn HidAdapter, protocol: &Protocol) -> Result<(), HidError> {
+        Packet::Command(*self).send(device, protocol)
+    }
+}
+
+/// Running totals of the packet work done by `present` and `send_region`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PresentStats {
+    /// Packets built from the back buffer, whether or not they then needed sending
+    pub packets_built: usize,
+    /// Packets written to the device
+    pub packets_sent: usize,
+}
+
+/// Retries for transient USB errors: a failed packet write is retried up to `retries` times,
+/// `delay` apart, before `present` gives up on the frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SendPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl Default for SendPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            delay: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Clockwise rotation applied to everything drawn on a `Screen`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+/// How a drawing operation combines with the pixels already on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawMode {
+    Set,
+    Clear,
+    Xor,
+}
+
+/// How `Screen::draw_image` reduces grayscale to on/off pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DitherMode {
+    /// A hard threshold, which loses gradients but keeps line art crisp
+    #[default]
+    None,
+    /// Ordered dithering, a regular pattern that stays stable between frames
+    Bayer4x4,
+    /// Error diffusion, the closest approximation of photos and gradients
+    FloydSteinberg,
+}
+
+/// Pixels hidden at each edge of the panel, e.g. behind a case bezel
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Margins {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+#[derive(Debug)]
+pub enum ScreenError {
+    Io(std::io::Error),
+    Font(&'static str),
+    Image(image::ImageError),
+    /// A bitmap's byte count doesn't match its dimensions
+    BitmapSize {
+        expected: usize,
+        actual: usize,
+    },
+    /// A strftime string chrono couldn't format
+    TimeFormat(String),
+    /// A pixel aspect ratio that isn't a positive, finite number
+    Aspect(f32),
+    /// A write to the keyboard failed, usually because it was unplugged or went to sleep
+    Disconnected(HidError),
+    /// The framebuffer needs more packets than the index byte can number
+    TooManyPackets {
+        packets: usize,
+        max: usize,
+    },
+    /// A protocol payload with no room for framebuffer bytes
+    PayloadSize(usize),
+    /// A screen with a zero width or height
+    EmptyScreen {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Display for ScreenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenError::Io(e) => write!(f, "I/O error: {e}"),
+            ScreenError::Font(e) => write!(f, "failed to parse font: {e}"),
+            ScreenError::Image(e) => write!(f, "image error: {e}"),
+            ScreenError::BitmapSize { expected, actual } => {
+                write!(f, "bitmap should be {expected} bytes, got {actual}")
+            }
+            ScreenError::TimeFormat(format) => write!(f, "invalid time format `{format}`"),
+            ScreenError::Aspect(aspect) => write!(f, "invalid pixel aspect ratio {aspect}"),
+            ScreenError::Disconnected(e) => write!(f, "device disconnected: {e}"),
+            ScreenError::TooManyPackets { packets, max } => {
+                write!(
+                    f,
+                    "screen needs {packets} packets, at most {max} are supported"
+                )
+            }
+            ScreenError::PayloadSize(size) => write!(f, "payload size {size} is too small"),
+            ScreenError::EmptyScreen { width, height } => {
+                write!(f, "a {width}x{height} screen has no pixels")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScreenError {}
+
+impl From<std::io::Error> for ScreenError {
+    fn from(e: std::io::Error) -> Self {
+        ScreenError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for ScreenError {
+    fn from(e: image::ImageError) -> Self {
+        ScreenError::Image(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Stroke weight for text. Bold is synthesised by overdrawing each glyph one pixel along,
+/// which makes every glyph one pixel wider.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FontWeight {
+    #[default]
+    Normal,
+    Bold,
+}
+
+impl FontWeight {
+    /// Extra pixels a glyph occupies at this weight
+    fn extra_width(self) -> isize {
+        match self {
+            FontWeight::Normal => 0,
+            FontWeight::Bold => 1,
+        }
+    }
+}
+
+pub struct Screen {
     width: usize,
     height: usize,
+    /// Packed back buffer, one bit per pixel. Bytes run column-major: each byte holds eight
+    /// horizontally adjacent pixels of one row, with the leftmost pixel in the least significant
+    /// bit, and a column of `height` such bytes covers a strip eight pixels wide. The byte for
+    /// (x, y) is therefore `(x / 8) * height + y`, at `get_bit_at_index` index `7 - (x % 8)`.
+    /// When the width isn't a multiple of 8 the last strip's unused high bits are padding.
     data: Vec<u8>,
-    _prev_packets: Option<Vec<DataPacket>>,
+    /// What the device is showing, as of the last `present`. `None` when unknown, which makes
+    /// the next `present` send every packet.
+    front: Option<Vec<u8>>,
     device: Box<dyn HidAdapter>,
+    filter: DeviceFilter,
+    protocol: Protocol,
+    default_font: Rc<Font>,
+    fallback_font: Option<Rc<Font>>,
+    fonts: HashMap<String, Rc<Font>>,
+    packet_delay: Duration,
+    send_policy: SendPolicy,
+    clock: Rc<dyn Clock>,
+    stats: PresentStats,
+    aspect: f32,
+    margins: Margins,
+    rotation: Rotation,
+    mirror_horizontal: bool,
+    mirror_vertical: bool,
+    line_spacing: isize,
+    full_redraw_on_clear: bool,
+    alpha_threshold: u8,
+    invert_output: bool,
 }
 
 impl Display for Screen {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string = self
-            .data
-            .iter()
-            .chunks(self.width / 8)
-            .into_iter()
-            .map(|row| row.map(|byte| format!("{byte:08b}")).join(""))
-            .join("\n")
-            .replace("0", "░")
-            .replace("1", "▓");
+        // Bytes run down columns (see `data`), so walk pixels row by row rather than bytes
+        let string = (0..self.height)
+            .map(|y| {
+                (0..self.width)
+                    .map(|x| if self.raw_pixel(x, y) { '▓' } else { '░' })
+                    .collect::<String>()
+            })
+            .join("\n");
 
         f.write_str(&string)
     }
@@ -87,34 +555,112 @@ impl Screen {
         device: impl HidAdapter + 'static,
         width: usize,
         height: usize,
-    ) -> Result<Self, HidError> {
+    ) -> Result<Self, ScreenError> {
+        if width == 0 || height == 0 {
+            return Err(ScreenError::EmptyScreen { width, height });
+        }
+
+        check_packet_count(width, height, &Protocol::default())?;
+
+        let default_font = Rc::new(
+            Font::from_bytes(
+                include_bytes!("../NANOTYPE.ttf") as &[u8],
+                fontdue::FontSettings::default(),
+            )
+            .expect("embedded font should parse"),
+        );
+
         Ok(Self {
-            data: vec![0; (width * height) / 8],
+            data: vec![0; buffer_len(width, height)],
             device: Box::new(device),
+            filter: DeviceFilter::default(),
+            protocol: Protocol::default(),
+            fallback_font: Some(default_font.clone()),
+            default_font,
+            fonts: HashMap::new(),
             width,
             height,
-            _prev_packets: None,
+            front: None,
+            packet_delay: Duration::ZERO,
+            send_policy: SendPolicy::default(),
+            clock: Rc::new(SystemClock),
+            stats: PresentStats::default(),
+            aspect: 1.0,
+            margins: Margins::default(),
+            rotation: Rotation::default(),
+            mirror_horizontal: false,
+            mirror_vertical: false,
+            line_spacing: 0,
+            full_redraw_on_clear: false,
+            alpha_threshold: 128,
+            invert_output: false,
         })
     }
 
     pub(crate) fn to_packets(&self) -> Vec<DataPacket> {
         self.data
             .iter()
-            .chunks(PAYLOAD_SIZE - 2)
+            .chunks(self.protocol.chunk_size())
             .into_iter()
             .map(|chunk| {
-                let mut output_array: [u8; PAYLOAD_SIZE - 2] = [0; PAYLOAD_SIZE - 2];
-                chunk
-                    .take(PAYLOAD_SIZE - 2)
-                    .enumerate()
-                    .for_each(|(index, byte)| output_array[index] = *byte);
+                let mut output_array = vec![0; self.protocol.chunk_size()];
+                chunk.enumerate().for_each(|(index, byte)| {
+                    output_array[index] = if self.invert_output { !byte } else { *byte }
+                });
                 output_array
             })
             .enumerate()
-            .map(|(index, chunk)| DataPacket::new(index.try_into().unwrap(), chunk))
+            .map(|(index, chunk)| {
+                DataPacket::new(
+                    index
+                        .try_into()
+                        .expect("from_device and set_protocol cap the packet count"),
+                    chunk,
+                )
+            })
             .collect()
     }
 
+    /// Reassembles a `width` x `height` framebuffer from packets made by `to_packets`. Packets
+    /// may arrive in any order; bytes no packet covers are left zeroed.
+    #[cfg(test)]
+    pub(crate) fn from_packets(packets: &[DataPacket], width: usize, height: usize) -> Vec<u8> {
+        let mut data = vec![0; buffer_len(width, height)];
+
+        for packet in packets {
+            let chunk_size = packet.payload.len();
+            let start = (packet.index as usize * chunk_size).min(data.len());
+            let end = (start + chunk_size).min(data.len());
+            data[start..end].copy_from_slice(&packet.payload[..end - start]);
+        }
+
+        data
+    }
+
+    /// Parses the font at `path` and caches it, so later draws using it don't touch the disk
+    pub fn load_font(&mut self, path: &str) -> Result<(), ScreenError> {
+        let font_bytes = fs::read(path)?;
+        let font = Font::from_bytes(font_bytes, fontdue::FontSettings::default())
+            .map_err(ScreenError::Font)?;
+        self.fonts.insert(path.to_string(), Rc::new(font));
+
+        Ok(())
+    }
+
+    /// Looks up a font in the cache, loading it on first use. `None` is the embedded font.
+    fn font(&mut self, font_path: Option<&str>) -> Result<Rc<Font>, ScreenError> {
+        let Some(font_path) = font_path else {
+            return Ok(self.default_font.clone());
+        };
+
+        if !self.fonts.contains_key(font_path) {
+            self.load_font(font_path)?;
+        }
+
+        Ok(self.fonts[font_path].clone())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn draw_text(
         &mut self,
         text: &str,
@@ -123,133 +669,624 @@ impl Screen {
         size: f32,
         font_path: Option<&str>,
         spacing: isize,
-    ) {
-        let font = if let Some(font_path) = font_path {
-            let font_bytes = fs::read(&font_path).unwrap();
-            Font::from_bytes(font_bytes, fontdue::FontSettings::default()).unwrap()
-        } else {
-            Font::from_bytes(
-                include_bytes!("../NANOTYPE.ttf") as &[u8],
-                fontdue::FontSettings::default(),
-            )
-            .unwrap()
-        };
+        weight: FontWeight,
+    ) -> Result<(), ScreenError> {
+        let font = self.font(font_path)?;
+        let fallback = self.fallback_font.clone();
+        let line_height = self.line_height(&font, size);
 
-        let mut x_cursor = x;
+        for (line_index, line) in text.split('\n').enumerate() {
+            let line_y = y + line_index as isize * line_height;
+            let (pens, _) = layout_line(
+                &font,
+                fallback.as_deref(),
+                line,
+                size,
+                spacing + weight.extra_width(),
+            );
 
-        for letter in text.chars() {
-            let width = font.metrics(letter, size).width as isize;
-            self.draw_letter(letter, x_cursor, y, size, &font);
+            for (letter, pen) in line.chars().zip(pens) {
+                let pen_x = x + pen.round() as isize;
 
-            // FIXME: Use horizontal kerning as opposed to abstract value of "2"
-            x_cursor += width + spacing;
+                match glyph_font(&font, fallback.as_deref(), letter) {
+                    Some(glyph_font) => {
+                        let metrics = glyph_font.metrics(letter, size);
+                        let glyph_x = pen_x + metrics.xmin as isize;
+                        self.draw_letter(letter, glyph_x, line_y, size, glyph_font, weight);
+                    }
+                    None => self.draw_placeholder(pen_x, line_y, size, &font),
+                }
+            }
         }
+
+        Ok(())
     }
 
-    fn draw_time(&mut self, time: SystemTime, font_size: f64, font_path: Option<String>) {
-        let font = if let Some(font_path) = font_path {
-            let font_bytes = fs::read(&font_path).unwrap();
-            Font::from_bytes(font_bytes, fontdue::FontSettings::default()).unwrap()
-        } else {
-            Font::from_bytes(
-                include_bytes!("../NANOTYPE.ttf") as &[u8],
-                fontdue::FontSettings::default(),
-            )
-            .unwrap()
-        };
+    /// Distance between the tops of consecutive lines of text, including `line_spacing`
+    fn line_height(&self, font: &Font, size: f32) -> isize {
+        let natural = font
+            .horizontal_line_metrics(size)
+            .map(|metrics| metrics.new_line_size)
+            .unwrap_or(size);
 
-        let formatted_time: DateTime<Local> = time.into();
-        let time_string = formatted_time.format("%I:%M %p").to_string();
-        let mut width_needed = 0;
+        natural.round() as isize + self.line_spacing
+    }
 
-        time_string.chars().into_iter().for_each(|c| {
-            let (metrics, _) = font.rasterize(c, font_size as f32);
-            width_needed += metrics.width as isize + font_size as isize / 24;
-        });
+    /// Extra pixels left between lines when text contains newlines. Defaults to zero.
+    pub fn set_line_spacing(&mut self, line_spacing: isize) {
+        self.line_spacing = line_spacing;
+    }
+
+    /// Horizontal distance the pen moves when `draw_text` renders `text` with the same
+    /// `spacing`, taking the widest line for multi-line text. For bold text, add
+    /// `FontWeight::Bold`'s extra pixel to `spacing`.
+    pub fn measure_text(&self, text: &str, size: f32, font: &Font, spacing: isize) -> usize {
+        text.split('\n')
+            .map(|line| layout_line(font, self.fallback_font.as_deref(), line, size, spacing).1)
+            .fold(0.0, f32::max)
+            .round() as usize
+    }
+
+    /// Distance from where `draw_text` starts `text` to the far edge of its last lit glyph
+    /// column, taking the widest line. Unlike `measure_text` this leaves out the trailing
+    /// advance and spacing, so it is what lines text up flush against an edge.
+    fn ink_width(
+        &self,
+        text: &str,
+        size: f32,
+        font: &Font,
+        spacing: isize,
+        weight: FontWeight,
+    ) -> usize {
+        let fallback = self.fallback_font.as_deref();
+        let mut width = 0;
+
+        for line in text.split('\n') {
+            let (pens, _) = layout_line(font, fallback, line, size, spacing + weight.extra_width());
+
+            for (letter, pen) in line.chars().zip(pens) {
+                let extent = match glyph_font(font, fallback, letter) {
+                    Some(glyph_font) => {
+                        let metrics = glyph_font.metrics(letter, size);
+                        // Whitespace lights nothing to line up against
+                        if metrics.width == 0 {
+                            continue;
+                        }
+                        metrics.xmin as isize + metrics.width as isize + weight.extra_width()
+                    }
+                    None => placeholder_width(font, size),
+                };
 
-        self.draw_text(
+                width = width.max(pen.round() as isize + extent);
+            }
+        }
+
+        width.max(0) as usize
+    }
+
+    /// Pixel extent of `text` drawn with no extra spacing, as `(width, height)`. The height
+    /// covers every line from the first line's ascenders to the last line's descenders.
+    pub fn text_bounds(&self, text: &str, size: f32, font: &Font) -> (usize, usize) {
+        let width = self.measure_text(text, size, font, 0);
+        let extent = font
+            .horizontal_line_metrics(size)
+            .map(|metrics| metrics.ascent - metrics.descent)
+            .unwrap_or(size);
+        let lines = text.split('\n').count() as isize;
+        let height = (lines - 1) * self.line_height(font, size) + extent.ceil() as isize;
+
+        (width, height.max(0) as usize)
+    }
+
+    /// Draws the current time centered near the top, using a chrono strftime `format` such as
+    /// `DEFAULT_TIME_FORMAT` or `"%H:%M:%S"`
+    fn draw_time(
+        &mut self,
+        clock: &dyn Clock,
+        format: &str,
+        font_size: f64,
+        font_path: Option<String>,
+        weight: FontWeight,
+    ) -> Result<(), ScreenError> {
+        let time_string = format_time(clock, format)?;
+
+        self.draw_text_aligned(
             &time_string,
-            (128 - width_needed) / 2,
+            TextAlign::Center,
             10,
             font_size as f32,
-            None,
-            font_size as isize / 24,
+            font_path.as_deref(),
+            weight,
         )
     }
 
-    fn draw_letter(&mut self, letter: char, x: isize, y: isize, size: f32, font: &Font) {
+    fn draw_letter(
+        &mut self,
+        letter: char,
+        x: isize,
+        y: isize,
+        size: f32,
+        font: &Font,
+        weight: FontWeight,
+    ) {
         let (metrics, bitmap) = font.rasterize(letter, size);
 
-        let flipped = match letter {
-            '.' => bitmap,
-            _ => flip_vertical(&bitmap, metrics.width as usize, metrics.height as usize),
+        let (width, bitmap) = match weight {
+            FontWeight::Normal => (metrics.width, bitmap),
+            FontWeight::Bold => (
+                metrics.width + 1,
+                embolden(&bitmap, metrics.width, metrics.height),
+            ),
         };
 
-        for (index, byte) in flipped.into_iter().enumerate() {
-            let index = index as isize;
+        // `y` is the top of the capitals, putting the baseline a cap height further along.
+        // Each glyph is placed by its own bounds relative to that baseline, so short glyphs
+        // like '.' and descenders land where the font intends.
+        let glyph_top = cap_height(font, size) - (metrics.ymin as isize + metrics.height as isize);
 
-            let width = metrics.width as isize;
-            let height = metrics.height as isize;
+        for (index, byte) in bitmap.into_iter().enumerate() {
+            let index = index as isize;
+            let width = width as isize;
 
+            // The bitmap is row-major, top row first
             let row = x + (index % width);
-            let col = y + height - (index / width);
-            let enabled = (byte as f32 / 255.0).round() as i32 == 1;
-            self.set_pixel(col, row, enabled)
+            let offset = glyph_top + index / width;
+            let enabled = byte >= self.alpha_threshold;
+
+            // Glyph rows run along the x axis, so stretch them to correct for the aspect
+            let col_start = y + self.scale_x(offset);
+            let col_end = (y + self.scale_x(offset + 1)).max(col_start + 1);
+            for col in col_start..col_end {
+                self.set_pixel(col, row, enabled)
+            }
         }
     }
 
-    fn render_centered(&mut self, text: String, font_size: f64, y: usize, font_path: Option<&str>) {
-        let font = if let Some(font_path) = font_path {
-            let font_bytes = fs::read(&font_path).unwrap();
-            Font::from_bytes(font_bytes, fontdue::FontSettings::default()).unwrap()
-        } else {
-            Font::from_bytes(
-                include_bytes!("../NANOTYPE.ttf") as &[u8],
-                fontdue::FontSettings::default(),
-            )
-            .unwrap()
-        };
+    /// Draws an outlined box in place of a character no font has a glyph for
+    fn draw_placeholder(&mut self, x: isize, y: isize, size: f32, font: &Font) {
+        let height = self.scale_x(cap_height(font, size));
+        self.draw_rect(y, x, height, placeholder_width(font, size), true);
+    }
 
-        let mut width_needed = 0;
+    /// Font used for characters missing from the one being drawn with, loading it like
+    /// `draw_text` does. `None` draws a placeholder box instead. Defaults to the embedded font.
+    pub fn set_fallback_font(&mut self, font_path: Option<&str>) -> Result<(), ScreenError> {
+        self.fallback_font = match font_path {
+            Some(font_path) => Some(self.font(Some(font_path))?),
+            None => None,
+        };
 
-        text.chars().into_iter().for_each(|c| {
-            let (metrics, _) = font.rasterize(c, font_size as f32);
-            width_needed += metrics.width as usize + font_size as usize / 24;
-        });
+        Ok(())
+    }
 
-        self.draw_text(
+    fn render_centered(
+        &mut self,
+        text: String,
+        font_size: f64,
+        y: usize,
+        font_path: Option<&str>,
+        weight: FontWeight,
+    ) -> Result<(), ScreenError> {
+        self.draw_text_aligned(
             &text,
-            ((128 - width_needed) / 2).try_into().unwrap(),
-            y.try_into().unwrap(),
+            TextAlign::Center,
+            y as isize,
             font_size as f32,
             font_path,
-            font_size as isize / 24,
-        );
+            weight,
+        )
     }
 
-    pub fn send(&mut self) -> Result<(), HidError> {
-        let mut packets = self.to_packets();
-
-        // Filter out packets for regions of the screen which haven't changed since last time
-        if let Some(prev_packets) = &self._prev_packets {
-            packets.retain(|packet| !prev_packets.contains(packet))
-        };
-
-        self._prev_packets = Some(self.to_packets());
+    /// Draws `text` on line `y`, aligned against the drawable area. Text runs along the long
+    /// edge of the panel, so alignment is measured against the inset height.
+    pub fn draw_text_aligned(
+        &mut self,
+        text: &str,
+        align: TextAlign,
+        y: isize,
+        size: f32,
+        font_path: Option<&str>,
+        weight: FontWeight,
+    ) -> Result<(), ScreenError> {
+        let font = self.font(font_path)?;
 
-        for packet in packets {
-            packet.send(self.device.as_ref())?;
-        }
+        let spacing = default_spacing(size);
+        let width = self.ink_width(text, size, &font, spacing, weight) as isize;
+        let available = self.inset_height() as isize;
 
-        Ok(())
-    }
+        // Text wider than the screen falls back to left alignment
+        let x = match align {
+            TextAlign::Left => 0,
+            TextAlign::Center => (available - width).max(0) / 2,
+            TextAlign::Right => (available - width).max(0),
+        };
 
-    pub fn clear(&mut self) {
-        self.data = vec![0; (self.width * self.height) / 8_usize];
+        self.draw_text(text, x, y, size, font_path, spacing, weight)
+    }
+
+    /// Draws `text` in the embedded font, breaking lines at word boundaries so none is wider
+    /// than `max_width`. Words too long for a line on their own are broken between characters.
+    /// Returns how many lines were drawn.
+    pub fn draw_text_wrapped(
+        &mut self,
+        text: &str,
+        x: isize,
+        y: isize,
+        max_width: usize,
+        line_height: isize,
+        size: f32,
+    ) -> Result<usize, ScreenError> {
+        let font = self.font(None)?;
+        let spacing = default_spacing(size);
+        let fits = |line: &str| self.measure_text(line, size, &font, spacing) <= max_width;
+
+        let mut lines: Vec<String> = Vec::new();
+
+        for paragraph in text.split('\n') {
+            let mut current = String::new();
+
+            for word in paragraph.split_whitespace() {
+                let candidate = if current.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{current} {word}")
+                };
+
+                if fits(&candidate) {
+                    current = candidate;
+                    continue;
+                }
+
+                if !current.is_empty() {
+                    lines.push(std::mem::take(&mut current));
+                }
+
+                // Hard-break words that can't fit on a line of their own
+                for letter in word.chars() {
+                    current.push(letter);
+                    if !fits(&current) && current.chars().count() > 1 {
+                        current.pop();
+                        lines.push(std::mem::take(&mut current));
+                        current.push(letter);
+                    }
+                }
+            }
+
+            lines.push(current);
+        }
+
+        for (index, line) in lines.iter().enumerate() {
+            let line_y = y + index as isize * line_height;
+            self.draw_text(line, x, line_y, size, None, spacing, FontWeight::Normal)?;
+        }
+
+        Ok(lines.len())
+    }
+
+    /// Largest font size (clamped to `min_size..=max_size`) at which `line_count` lines fit
+    /// across the panel, with `spacing_ratio` of a line height left between consecutive lines.
+    ///
+    /// Text runs along the long edge of the panel, so lines stack across the inset width: that
+    /// is the text's height, even though the panel's own `height` is the 128 pixel edge.
+    pub fn fit_lines(
+        &self,
+        line_count: usize,
+        spacing_ratio: f32,
+        max_size: f32,
+        min_size: f32,
+        font: &Font,
+    ) -> f32 {
+        if line_count == 0 {
+            return max_size;
+        }
+
+        // Line metrics scale linearly with the pixel size, so measure once at 1px
+        let line_height = font
+            .horizontal_line_metrics(1.0)
+            .map(|metrics| metrics.new_line_size)
+            .unwrap_or(1.0);
+
+        let lines = line_count as f32 + (line_count - 1) as f32 * spacing_ratio;
+        let size = self.inset_width() as f32 / (line_height * lines);
+
+        size.min(max_size).max(min_size)
+    }
+
+    /// Largest whole font size up to `max_size`, and no smaller than 1, at which `text` is at
+    /// most `max_width` long and its capitals at most `max_height` tall
+    pub fn fit_text(
+        &self,
+        text: &str,
+        max_width: usize,
+        max_height: isize,
+        max_size: f32,
+        font: &Font,
+    ) -> f32 {
+        let fits = |size: f32| {
+            let width = self.ink_width(text, size, font, default_spacing(size), FontWeight::Normal);
+            width <= max_width && cap_height(font, size) <= max_height
+        };
+
+        let mut size = max_size.floor();
+        while size > 1.0 && !fits(size) {
+            size -= 1.0;
+        }
+
+        size.max(1.0)
+    }
+
+    /// Sends the parts of the back buffer that differ from what the device is showing, then
+    /// makes the back buffer the new front. Drawing only ever touches the back buffer.
+    pub fn present(&mut self) -> Result<(), ScreenError> {
+        if self.front.as_ref() == Some(&self.data) {
+            return Ok(());
+        }
+
+        let packets = self.to_packets();
+        self.stats.packets_built += packets.len();
+        let changed: Vec<&DataPacket> = packets
+            .iter()
+            .filter(|packet| self.packet_changed(packet.index as usize))
+            .collect();
+
+        for (index, packet) in changed.into_iter().enumerate() {
+            // Space out consecutive writes for MCUs that drop packets arriving back to back
+            if index > 0 && !self.packet_delay.is_zero() {
+                self.clock.sleep(self.packet_delay);
+            }
+
+            self.send_packet(packet)
+                .map_err(ScreenError::Disconnected)?;
+            self.stats.packets_sent += 1;
+        }
+
+        self.front = Some(self.data.clone());
+
+        Ok(())
+    }
+
+    /// Range of framebuffer bytes carried by the packet at `index`
+    fn packet_range(&self, index: usize) -> Range<usize> {
+        let chunk_size = self.protocol.chunk_size();
+        let start = index * chunk_size;
+        start.min(self.data.len())..(start + chunk_size).min(self.data.len())
+    }
+
+    /// Whether the packet at `index` differs between the back and front buffers
+    fn packet_changed(&self, index: usize) -> bool {
+        let range = self.packet_range(index);
+        match &self.front {
+            Some(front) => front[range.clone()] != self.data[range],
+            None => true,
+        }
+    }
+
+    /// Like `present`, but only considers packets overlapping the rectangle between
+    /// (`min_x`, `min_y`) and (`max_x`, `max_y`) inclusive, in the same coordinates as
+    /// `set_pixel`. Useful for small animations where the caller knows exactly what changed.
+    pub fn send_region(
+        &mut self,
+        min_x: usize,
+        min_y: usize,
+        max_x: usize,
+        max_y: usize,
+    ) -> Result<(), ScreenError> {
+        let max_x = max_x.min(self.inset_width().saturating_sub(1));
+        let max_y = max_y.min(self.inset_height().saturating_sub(1));
+        if min_x > max_x || min_y > max_y || self.data.is_empty() {
+            return Ok(());
+        }
+
+        // Rotation and mirroring can swap corners around, so take the bounds of all four
+        let corners = [
+            (min_x, min_y),
+            (max_x, min_y),
+            (min_x, max_y),
+            (max_x, max_y),
+        ]
+        .map(|(x, y)| self.to_physical(x + self.margins.left, y + self.margins.top));
+        let (x0, x1) = corners
+            .iter()
+            .map(|&(x, _)| x)
+            .minmax()
+            .into_option()
+            .unwrap();
+        let (y0, y1) = corners
+            .iter()
+            .map(|&(_, y)| y)
+            .minmax()
+            .into_option()
+            .unwrap();
+
+        let chunk_size = self.protocol.chunk_size();
+        let indices: Vec<usize> = (x0 / 8..=x1 / 8)
+            .flat_map(|strip| {
+                let first = self.bit_position(strip * 8, y0).0;
+                let last = self.bit_position(strip * 8, y1).0;
+                first / chunk_size..=last / chunk_size
+            })
+            .sorted()
+            .dedup()
+            .collect();
+
+        let packets = self.to_packets();
+        self.stats.packets_built += packets.len();
+
+        let mut sent = 0;
+        for &index in &indices {
+            let Some(packet) = packets.get(index) else {
+                break;
+            };
+
+            if !self.packet_changed(index) {
+                continue;
+            }
+
+            if sent > 0 && !self.packet_delay.is_zero() {
+                self.clock.sleep(self.packet_delay);
+            }
+
+            self.send_packet(packet)
+                .map_err(ScreenError::Disconnected)?;
+            sent += 1;
+            self.stats.packets_sent += 1;
+
+            let range = self.packet_range(index);
+            if let Some(front) = &mut self.front {
+                front[range.clone()].copy_from_slice(&self.data[range]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes one packet, retrying failed writes as the `SendPolicy` allows
+    fn send_packet(&self, packet: &DataPacket) -> Result<(), HidError> {
+        let mut attempt = 0;
+
+        loop {
+            match packet.send(self.device.as_ref(), &self.protocol) {
+                Err(_) if attempt < self.send_policy.retries => {
+                    attempt += 1;
+                    self.clock.sleep(self.send_policy.delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Like `present`, but on `ScreenError::Disconnected` keeps trying to reopen the device with an
+    /// increasing delay until a full frame gets through.
+    pub fn present_or_reconnect(&mut self, api: &mut HidApi) -> Result<(), ScreenError> {
+        match self.present() {
+            Err(ScreenError::Disconnected(e)) => warn!("device disconnected: {e}"),
+            result => return result,
+        }
+
+        let mut backoff = RECONNECT_BACKOFF_MIN;
+        loop {
+            debug!("reconnecting in {backoff:?}");
+            thread::sleep(backoff);
+
+            match self.reconnect(api) {
+                Ok(()) => match self.present() {
+                    Err(ScreenError::Disconnected(e)) => warn!("device disconnected: {e}"),
+                    result => {
+                        info!("reconnected to device");
+                        return result;
+                    }
+                },
+                Err(e) => debug!("reconnect failed: {e}"),
+            }
+
+            backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+        }
+    }
+
+    /// Re-enumerates devices and swaps in the first one matching the screen's `DeviceFilter`.
+    /// The front buffer is dropped so the next `present` writes the whole frame.
+    pub fn reconnect(&mut self, api: &mut HidApi) -> Result<(), HidError> {
+        api.refresh_devices()?;
+
+        let device = find_device(api, &self.filter)
+            .ok_or_else(|| HidError::HidApiError {
+                message: "no matching device found".to_string(),
+            })?
+            .open_device(api)?;
+
+        self.device = Box::new(device);
+        self.force_full_redraw();
+
+        Ok(())
+    }
+
+    /// Sets the panel's contrast (brightness), from 0 (dimmest) to 255. Takes effect
+    /// immediately, independent of `present`.
+    pub fn set_contrast(&mut self, level: u8) -> Result<(), ScreenError> {
+        Command::SetContrast(level)
+            .send(self.device.as_ref(), &self.protocol)
+            .map_err(ScreenError::Disconnected)
+    }
+
+    /// Forgets what was last sent, so the next `present` writes every packet. Use this when the
+    /// device's contents may no longer match, e.g. after it was reset.
+    pub fn force_full_redraw(&mut self) {
+        self.front = None;
+    }
+
+    /// Complements every byte as it is sent, for white-on-black output, while drawing and
+    /// `get_pixel` keep working on the uninverted image. Off by default.
+    pub fn set_invert_output(&mut self, enabled: bool) {
+        if self.invert_output != enabled {
+            self.invert_output = enabled;
+            self.force_full_redraw();
+        }
+    }
+
+    /// When enabled, every `clear` also calls `force_full_redraw`. Off by default.
+    pub fn set_full_redraw_on_clear(&mut self, enabled: bool) {
+        self.full_redraw_on_clear = enabled;
+    }
+
+    /// Wire format to speak to the device. Defaults to `Protocol::default()`. Packets need room
+    /// for at least one framebuffer byte after the report id and index.
+    pub fn set_protocol(&mut self, protocol: Protocol) -> Result<(), ScreenError> {
+        if protocol.payload_size < 3 {
+            return Err(ScreenError::PayloadSize(protocol.payload_size));
+        }
+        check_packet_count(self.width, self.height, &protocol)?;
+
+        self.protocol = protocol;
+        self.force_full_redraw();
+
+        Ok(())
+    }
+
+    /// Which device `reconnect` looks for. Defaults to `DeviceFilter::default()`.
+    pub fn set_device_filter(&mut self, filter: DeviceFilter) {
+        self.filter = filter;
+    }
+
+    pub fn device(&self) -> &dyn HidAdapter {
+        self.device.as_ref()
+    }
+
+    /// Pause for `delay` between consecutive packet writes in `present`. Defaults to zero.
+    pub fn set_packet_delay(&mut self, delay: Duration) {
+        self.packet_delay = delay;
+    }
+
+    /// How `present` retries packets whose write fails. Defaults to no retries.
+    pub fn set_send_policy(&mut self, policy: SendPolicy) {
+        self.send_policy = policy;
+    }
+
+    pub fn stats(&self) -> PresentStats {
+        self.stats
+    }
+
+    /// Clock used for the packet delay and retry waits. Defaults to `SystemClock`.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn clear(&mut self) {
+        self.data = vec![0; buffer_len(self.width, self.height)];
+
+        if self.full_redraw_on_clear {
+            self.force_full_redraw();
+        }
     }
 
     pub fn fill_all(&mut self) {
-        self.data = vec![1; (self.width * self.height) / 8_usize];
+        self.fill_pattern(0xFF);
+    }
+
+    /// Sets every framebuffer byte to `pattern`, for striped or dithered backgrounds. Each byte
+    /// is eight horizontally adjacent pixels of one physical row, leftmost in the least
+    /// significant bit, so `0x55` lights every other column. Margins and rotation are ignored.
+    pub fn fill_pattern(&mut self, pattern: u8) {
+        self.data.fill(pattern);
     }
 
     pub fn paint_region(
@@ -260,151 +1297,3099 @@ impl Screen {
         max_y: isize,
         enabled: bool,
     ) {
+        let mode = if enabled {
+            DrawMode::Set
+        } else {
+            DrawMode::Clear
+        };
+        self.paint_region_mode(min_x, min_y, max_x, max_y, mode);
+    }
+
+    /// Applies `mode` to every pixel in the region, clipped to the drawable area
+    pub fn paint_region_mode(
+        &mut self,
+        min_x: isize,
+        min_y: isize,
+        max_x: isize,
+        max_y: isize,
+        mode: DrawMode,
+    ) {
+        let min_x = min_x.max(0);
+        let min_y = min_y.max(0);
+        let max_x = max_x.min(self.inset_width() as isize);
+        let max_y = max_y.min(self.inset_height() as isize);
+
         for x in min_x..max_x {
             for y in min_y..max_y {
-                self.set_pixel(x, y, enabled)
+                self.set_pixel_mode(x, y, mode)
             }
         }
     }
 
-    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
-        let byte_index = (x + y * self.width) / 8;
-        let bit_index: u8 = 7 - ((x % 8) as u8);
+    /// Copies a packed 1bpp `bmp_width`×`bmp_height` bitmap onto the screen at (`x`, `y`),
+    /// clipping at the edges. `data` uses the same layout as the framebuffer (see `data`), so
+    /// when nothing is transformed and the bitmap sits on a byte boundary whole bytes are copied
+    /// instead of going pixel by pixel.
+    pub fn blit_bitmap(
+        &mut self,
+        data: &[u8],
+        bmp_width: usize,
+        bmp_height: usize,
+        x: isize,
+        y: isize,
+    ) -> Result<(), ScreenError> {
+        let strips = bmp_width.div_ceil(8);
+        if data.len() != strips * bmp_height {
+            return Err(ScreenError::BitmapSize {
+                expected: strips * bmp_height,
+                actual: data.len(),
+            });
+        }
+
+        let untransformed = self.rotation == Rotation::Deg0
+            && !self.mirror_horizontal
+            && !self.mirror_vertical
+            && self.margins == Margins::default();
+
+        for strip in 0..strips {
+            let strip_x = x + strip as isize * 8;
+            let strip_width = (bmp_width - strip * 8).min(8);
+
+            // A strip landing wholly inside the screen on a byte boundary maps onto one column
+            // of framebuffer bytes
+            if untransformed
+                && strip_width == 8
+                && strip_x >= 0
+                && strip_x % 8 == 0
+                && strip_x as usize + 8 <= self.width
+            {
+                for by in 0..bmp_height {
+                    let py = y + by as isize;
+                    if py < 0 || py >= self.height as isize {
+                        continue;
+                    }
+
+                    let (byte_index, _) = self.bit_position(strip_x as usize, py as usize);
+                    self.data[byte_index] = data[strip * bmp_height + by];
+                }
+                continue;
+            }
+
+            for bx in 0..strip_width {
+                for by in 0..bmp_height {
+                    let byte = data[strip * bmp_height + by];
+                    let enabled = get_bit_at_index(byte, 7 - bx as u8);
+                    self.set_pixel(strip_x + bx as isize, y + by as isize, enabled);
+                }
+            }
+        }
 
-        let byte = self.data[byte_index];
-        get_bit_at_index(byte, bit_index)
+        Ok(())
     }
 
-    /// Underlying function for drawing to the canvas, if provided coordinates are out of range,
-    /// this function will fail silently
-    ///
-    /// # Arguments
-    /// * `x` - The x coordinate of the pixel to set
-    /// * `y` - The y coordinate of the pixel to set
-    /// * `enabled` - Whether to set the pixel to an enabled or disabled state (on/off)
-    pub fn set_pixel(&mut self, x: isize, y: isize, enabled: bool) {
-        if x >= self.width as isize || y >= self.height as isize || x < 0 || y < 0 {
-            // If a pixel is rendered outside of the canvas, fail silently
+    /// Toggles every pixel in the region, like `paint_region` but flipping each pixel's current
+    /// state. Handy for highlighting a selection without redrawing it.
+    pub fn invert_region(&mut self, min_x: isize, min_y: isize, max_x: isize, max_y: isize) {
+        self.paint_region_mode(min_x, min_y, max_x, max_y, DrawMode::Xor);
+    }
+
+    /// Toggles every pixel in the drawable area
+    pub fn invert(&mut self) {
+        self.invert_region(
+            0,
+            0,
+            self.inset_width() as isize,
+            self.inset_height() as isize,
+        );
+    }
+
+    /// Draws a one pixel wide line between two points (inclusive) using Bresenham's algorithm.
+    /// Points that fall outside the screen are skipped, like `set_pixel`.
+    pub fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, enabled: bool) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            self.set_pixel(x, y, enabled);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the one pixel border of a `w`×`h` rectangle with its top left corner at (`x`, `y`),
+    /// leaving the interior untouched. Nothing is drawn if either dimension is zero or negative.
+    pub fn draw_rect(&mut self, x: isize, y: isize, w: isize, h: isize, enabled: bool) {
+        if w <= 0 || h <= 0 {
             return;
         }
 
-        let x = x as usize;
-        let y = y as usize;
+        let (max_x, max_y) = (x + w - 1, y + h - 1);
 
-        let target_byte = (x / 8) * self.height + y;
-        let target_bit: u8 = 7 - ((x % 8) as u8);
+        self.draw_line(x, y, max_x, y, enabled);
+        self.draw_line(x, max_y, max_x, max_y, enabled);
+        self.draw_line(x, y, x, max_y, enabled);
+        self.draw_line(max_x, y, max_x, max_y, enabled);
+    }
 
-        self.data[target_byte] = set_bit_at_index(self.data[target_byte], target_bit, enabled);
+    /// Draws a circle of `radius` around (`cx`, `cy`) using the midpoint circle algorithm,
+    /// either as a one pixel outline or `filled`. Horizontal extents follow the pixel aspect
+    /// ratio, and anything past the screen edge is clipped.
+    pub fn draw_circle(
+        &mut self,
+        cx: isize,
+        cy: isize,
+        radius: isize,
+        filled: bool,
+        enabled: bool,
+    ) {
+        if radius < 0 {
+            return;
+        }
+
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+
+        while x >= y {
+            // Each step yields one point per octant; (x, y) and (y, x) cover all eight
+            for (dx, dy) in [(x, y), (y, x)] {
+                let dx = self.scale_x(dx);
+
+                if filled {
+                    self.draw_line(cx - dx, cy + dy, cx + dx, cy + dy, enabled);
+                    self.draw_line(cx - dx, cy - dy, cx + dx, cy - dy, enabled);
+                } else {
+                    self.set_pixel(cx + dx, cy + dy, enabled);
+                    self.set_pixel(cx - dx, cy + dy, enabled);
+                    self.set_pixel(cx + dx, cy - dy, enabled);
+                    self.set_pixel(cx - dx, cy - dy, enabled);
+                }
+            }
+
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
     }
-}
 
-fn flip_vertical(bitmap: &Vec<u8>, width: usize, height: usize) -> Vec<u8> {
-    let mut flipped = Vec::with_capacity(bitmap.len());
+    /// Loads the image at `path` (any format the `image` crate understands) and blits it with
+    /// its top left corner at (`x`, `y`), reducing it to one bit with `dither`. With
+    /// `DitherMode::None`, pixels brighter than `threshold` are lit. Transparent pixels are left
+    /// untouched and anything past the screen edge is clipped.
+    pub fn draw_image(
+        &mut self,
+        path: &str,
+        x: isize,
+        y: isize,
+        threshold: u8,
+        dither: DitherMode,
+    ) -> Result<(), ScreenError> {
+        let image = image::open(path)?.into_luma_alpha8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        let luma: Vec<u8> = image.pixels().map(|pixel| pixel.0[0]).collect();
+        let lit = match dither {
+            DitherMode::None => luma.iter().map(|value| *value > threshold).collect(),
+            DitherMode::Bayer4x4 => bayer4x4(&luma, width, height),
+            DitherMode::FloydSteinberg => floyd_steinberg(&luma, width, height),
+        };
+
+        for (index, pixel) in image.pixels().enumerate() {
+            if pixel.0[1] < 128 {
+                continue;
+            }
 
-    // Assuming 1 byte per pixel here; adjust if your bitmap uses more bytes per pixel.
-    let row_bytes = width;
+            let (px, py) = ((index % width) as isize, (index / width) as isize);
+            self.set_pixel(x + px, y + py, lit[index]);
+        }
 
-    for y in (0..height).rev() {
-        let row_start = y * row_bytes;
-        let row_end = (y + 1) * row_bytes;
-        flipped.extend_from_slice(&bitmap[row_start..row_end]);
+        Ok(())
     }
 
-    flipped
-}
+    /// Draws a raw 1bpp bitmap with its top left corner at (`x`, `y`). `bytes` is row-major,
+    /// each row padded to a whole byte, with the leftmost pixel in the most significant bit.
+    /// Both lit and unlit pixels are drawn, and anything past the screen edge is clipped.
+    pub fn draw_bitmap(
+        &mut self,
+        bytes: &[u8],
+        width: usize,
+        height: usize,
+        x: isize,
+        y: isize,
+    ) -> Result<(), ScreenError> {
+        let stride = width.div_ceil(8);
+        if bytes.len() != stride * height {
+            return Err(ScreenError::BitmapSize {
+                expected: stride * height,
+                actual: bytes.len(),
+            });
+        }
 
-pub fn get_bit_at_index(byte: u8, bit_index: u8) -> bool {
-    let mask = 0b10000000 >> bit_index;
+        for by in 0..height {
+            for bx in 0..width {
+                let byte = bytes[by * stride + bx / 8];
+                let enabled = get_bit_at_index(byte, (bx % 8) as u8);
+                self.set_pixel(x + bx as isize, y + by as isize, enabled);
+            }
+        }
 
-    mask & byte != 0
-}
+        Ok(())
+    }
 
-pub fn set_bit_at_index(byte: u8, bit_index: u8, enabled: bool) -> u8 {
-    let mask = 0b10000000 >> bit_index;
+    /// Draws a `size`×`size` box at (`x`, `y`), crossed through with an X when `checked`
+    pub fn draw_checkbox(&mut self, x: isize, y: isize, size: isize, checked: bool) {
+        let width = self.scale_x(size);
+        self.paint_region(x, y, x + width, y + size, false);
+        self.draw_rect(x, y, width, size, true);
 
-    if enabled {
-        mask | byte
-    } else {
-        (mask ^ 0b11111111) & byte
+        if checked {
+            for offset in 2..size - 2 {
+                let dx = self.scale_x(offset);
+                self.set_pixel(x + dx, y + offset, true);
+                self.set_pixel(x + width - 1 - dx, y + offset, true);
+            }
+        }
     }
-}
 
-fn main() {
-    let api = HidApi::new().unwrap_or_else(|e| {
-        eprintln!("Failed to initialize HID API: {}", e);
-        std::process::exit(1);
-    });
+    /// Draws a slider toggle: an outlined track with a solid knob sitting at the high-x end
+    /// when `on` and at the low-x end when off
+    pub fn draw_toggle(&mut self, x: isize, y: isize, w: isize, h: isize, on: bool) {
+        self.paint_region(x, y, x + w, y + h, false);
+        self.draw_rect(x, y, w, h, true);
 
-    let mut sys = System::new_all();
+        let knob = h - 4;
+        let knob_width = self.scale_x(knob);
+        let knob_x = if on { x + w - 2 - knob_width } else { x + 2 };
+        self.paint_region(knob_x, y + 2, knob_x + knob_width, y + 2 + knob, true);
+    }
 
-    // loop {
-    //     sys.refresh_cpu();
-    //     println!("cpu: {}%", sys.global_cpu_info().cpu_usage());
-    //     std::thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
-    // }
-
-    let device = api
-        .device_list()
-        .find(|device| is_my_device(device))
-        .unwrap_or_else(|| {
-            eprintln!("Failed to find device");
-            std::process::exit(1);
-        })
-        .open_device(&api)
-        .unwrap_or_else(|e| {
-            eprintln!("Failed to open device: {}", e);
-            std::process::exit(1);
-        });
+    /// Draws a horizontal gauge: an outlined `w` x `h` box whose interior is filled from the
+    /// low-x end for `fraction` of its width. `fraction` is clamped to 0.0..=1.0.
+    pub fn draw_gauge(
+        &mut self,
+        x: isize,
+        y: isize,
+        w: isize,
+        h: isize,
+        fraction: f32,
+        enabled: bool,
+    ) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
 
-    let mut screen = Screen::from_device(device, 62, 128).unwrap();
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
 
-    loop {
-        sys.refresh_cpu();
-        sys.refresh_memory();
-        let cpu_usage = sys.global_cpu_info().cpu_usage();
-        let current_ram = sys.used_memory();
+        self.draw_rect(x, y, w, h, enabled);
 
-        // clear bg
-        screen.clear();
+        let (inner_x, inner_y) = (x + 1, y + 1);
+        let (inner_w, inner_h) = (w - 2, h - 2);
+        let filled = (inner_w as f32 * fraction).round() as isize;
 
-        screen.draw_time(SystemTime::now(), 64.0, None);
-
-        // screen.draw_text("CPU:", 10, 10, 32.0, None);
-        // screen.draw_text(
-        //     &format!("{:.2}%", cpu_usage).to_string(),
-        //     40,
-        //     10,
-        //     32.0,
-        //     None,
-        // );
-        //
-        // screen.draw_text("MEM:", 10, 24, 32.0, None);
-        // screen.draw_text(
-        //     &format!(
-        //         "{:.2}/{:.2}GB",
-        //         bytes_to_gb(current_ram),
-        //         bytes_to_gb(total_ram)
-        //     )
-        //     .to_string(),
-        //     40,
-        //     24,
-        //     32.0,
-        //     None,
-        // );
-
-        let text = format!(
-            "C    {:.1}%         M    {:.1} G",
-            cpu_usage,
-            bytes_to_gb(current_ram),
+        self.paint_region(
+            inner_x,
+            inner_y,
+            inner_x + filled,
+            inner_y + inner_h,
+            enabled,
         );
+        self.paint_region(
+            inner_x + filled,
+            inner_y,
+            inner_x + inner_w,
+            inner_y + inner_h,
+            !enabled,
+        );
+    }
 
-        screen.render_centered(text, 32.0, 42, None);
+    /// Draws one bar per CPU core side by side across the `w` x `h` box at (`x`, `y`), each
+    /// rising from the bottom in proportion to its usage (0-100). With more cores than pixels
+    /// of width, neighbouring cores share a bar showing their average.
+    pub fn draw_core_bars(&mut self, usages: &[f32], x: isize, y: isize, w: isize, h: isize) {
+        if usages.is_empty() || w <= 0 || h <= 0 {
+            return;
+        }
 
-        screen.send().unwrap();
-        thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+        self.paint_region(x, y, x + w, y + h, false);
+
+        let bars = usages.len().min(w as usize);
+        let bar_width = w / bars as isize;
+        // Leave a gap between bars once they are wide enough to spare one
+        let gap = isize::from(bar_width >= 3);
+
+        for bar in 0..bars {
+            let cores = &usages[bar * usages.len() / bars..(bar + 1) * usages.len() / bars];
+            let usage = cores.iter().sum::<f32>() / cores.len() as f32;
+            let fraction = (usage / 100.0).clamp(0.0, 1.0);
+            let height = (fraction * h as f32).round() as isize;
+
+            let bar_x = x + bar as isize * bar_width;
+            self.paint_region(bar_x, y + h - height, bar_x + bar_width - gap, y + h, true);
+        }
     }
-}
 
-fn bytes_to_gb(bytes: u64) -> f64 {
-    bytes as f64 / (1 << 30) as f64
+    /// Draws a battery icon in the `w` x `h` box at (`x`, `y`), lying along the y axis like text
+    /// with its terminal at the high-y end. The body fills from the low-y end for `fraction` of
+    /// its length, and a plus is drawn across the middle while `charging`.
+    pub fn draw_battery(
+        &mut self,
+        x: isize,
+        y: isize,
+        w: isize,
+        h: isize,
+        fraction: f32,
+        charging: bool,
+    ) {
+        if w < 3 || h < 4 {
+            return;
+        }
+
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+
+        let body = h - 2;
+        self.draw_rect(x, y, w, body, true);
+        self.paint_region(x + w / 3, y + body, x + w - w / 3, y + h, true);
+
+        let filled = ((body - 2) as f32 * fraction).round() as isize;
+        self.paint_region(x + 1, y + 1, x + w - 1, y + 1 + filled, true);
+
+        if charging {
+            // Xor keeps the plus visible over both the filled and the empty part
+            let (cx, cy) = (x + w / 2, y + body / 2);
+            let arm = ((w - 3) / 2).max(1);
+            self.set_pixel_mode(cx, cy, DrawMode::Xor);
+            for d in 1..=arm {
+                self.set_pixel_mode(cx - d, cy, DrawMode::Xor);
+                self.set_pixel_mode(cx + d, cy, DrawMode::Xor);
+                self.set_pixel_mode(cx, cy - d, DrawMode::Xor);
+                self.set_pixel_mode(cx, cy + d, DrawMode::Xor);
+            }
+        }
+    }
+
+    /// A lit `draw_gauge`, for utilisation readouts like CPU and memory
+    pub fn draw_progress_bar(
+        &mut self,
+        x: isize,
+        y: isize,
+        width: isize,
+        height: isize,
+        fraction: f32,
+    ) {
+        self.draw_gauge(x, y, width, height, fraction, true);
+    }
+
+    /// Sets the physical width:height ratio of a single pixel. Shape and text helpers scale
+    /// their x extents by the inverse so output looks proportionate on non-square pixels.
+    /// Anything but a positive, finite ratio is rejected and leaves the current one in place.
+    pub fn set_aspect(&mut self, aspect: f32) -> Result<(), ScreenError> {
+        if !aspect.is_finite() || aspect <= 0.0 {
+            return Err(ScreenError::Aspect(aspect));
+        }
+
+        self.aspect = aspect;
+        Ok(())
+    }
+
+    /// Sets the glyph coverage (0-255) at which a text pixel is lit. Defaults to 128; lower
+    /// values keep more of the thin strokes of light fonts at small sizes.
+    pub fn set_alpha_threshold(&mut self, threshold: u8) {
+        self.alpha_threshold = threshold;
+    }
+
+    /// Converts an x extent measured in square pixels into panel pixels
+    fn scale_x(&self, dx: isize) -> isize {
+        (dx as f32 / self.aspect).round() as isize
+    }
+
+    /// Decodes the GIF at `path` into a looping player positioned at (`x`, `y`), drawing its
+    /// first frame. Pixels brighter than `threshold` are lit.
+    #[cfg(feature = "gif")]
+    pub fn play_gif(
+        &mut self,
+        path: &str,
+        x: isize,
+        y: isize,
+        threshold: u8,
+    ) -> Result<GifPlayer, gif::DecodingError> {
+        let player = GifPlayer::open(path, x, y, threshold)?;
+        player.draw(self);
+
+        Ok(player)
+    }
+
+    /// Copies every pixel of `canvas` onto the screen with its top left corner at (`x`, `y`)
+    pub fn blit_canvas(&mut self, canvas: &Canvas, x: isize, y: isize) {
+        for cy in 0..canvas.height {
+            for cx in 0..canvas.width {
+                let enabled = canvas.get_pixel(cx, cy);
+                self.set_pixel(x + cx as isize, y + cy as isize, enabled);
+            }
+        }
+    }
+
+    /// Copies the `w`×`h` rectangle at (`src_x`, `src_y`) of `src`, e.g. an off-screen buffer
+    /// holding a pre-rendered panel, to (`dst_x`, `dst_y`). Goes pixel by pixel in logical
+    /// coordinates, so the two screens may differ in size and orientation. Parts of the
+    /// rectangle outside either screen are skipped.
+    #[allow(clippy::too_many_arguments)]
+    pub fn blit(
+        &mut self,
+        src: &Screen,
+        src_x: isize,
+        src_y: isize,
+        w: usize,
+        h: usize,
+        dst_x: isize,
+        dst_y: isize,
+    ) {
+        let (src_width, src_height) = (src.inset_width() as isize, src.inset_height() as isize);
+
+        for dy in 0..h as isize {
+            for dx in 0..w as isize {
+                let (sx, sy) = (src_x + dx, src_y + dy);
+                if sx < 0 || sy < 0 || sx >= src_width || sy >= src_height {
+                    continue;
+                }
+
+                let enabled = src.get_pixel(sx as usize, sy as usize);
+                self.set_pixel(dst_x + dx, dst_y + dy, enabled);
+            }
+        }
+    }
+
+    /// Pixel states in a rectangle, row by row, for checking what was drawn where. Pixels outside
+    /// the screen read as off.
+    pub fn get_region(&self, x: isize, y: isize, w: usize, h: usize) -> Vec<bool> {
+        self.capture(x, y, w, h).pixels
+    }
+
+    /// Snapshots a region of the screen into a `Canvas`. Pixels outside the screen read as off.
+    pub fn capture(&self, x: isize, y: isize, width: usize, height: usize) -> Canvas {
+        let mut canvas = Canvas::new(width, height);
+
+        for cy in 0..height {
+            for cx in 0..width {
+                let (sx, sy) = (x + cx as isize, y + cy as isize);
+                if sx < 0
+                    || sy < 0
+                    || sx >= self.inset_width() as isize
+                    || sy >= self.inset_height() as isize
+                {
+                    continue;
+                }
+
+                canvas.set_pixel(cx, cy, self.get_pixel(sx as usize, sy as usize));
+            }
+        }
+
+        canvas
+    }
+
+    /// Insets all drawing by `margins`. Coordinates passed to the drawing methods become
+    /// relative to the top left of the inset area, and anything outside it is clipped. Edges are
+    /// named as drawing code sees them, after rotation.
+    pub fn set_margins(&mut self, margins: Margins) {
+        self.margins = margins;
+    }
+
+    /// Rotates everything drawn from now on. Quarter turns swap the width and height that
+    /// drawing code sees, while the framebuffer keeps the panel's native layout.
+    pub fn set_rotation(&mut self, rotation: Rotation) {
+        self.rotation = rotation;
+    }
+
+    /// Width and height as seen by drawing code, after rotation
+    fn logical_size(&self) -> (usize, usize) {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => (self.width, self.height),
+            Rotation::Deg90 | Rotation::Deg270 => (self.height, self.width),
+        }
+    }
+
+    /// Flips the whole composed frame along the panel's axes, for panels whose ribbon
+    /// orientation produces a mirrored image. Applied after rotation.
+    pub fn set_mirror(&mut self, horizontal: bool, vertical: bool) {
+        self.mirror_horizontal = horizontal;
+        self.mirror_vertical = vertical;
+    }
+
+    /// Maps a rotated position (margins already applied) to its position on the panel
+    fn to_physical(&self, x: usize, y: usize) -> (usize, usize) {
+        let (x, y) = match self.rotation {
+            Rotation::Deg0 => (x, y),
+            Rotation::Deg90 => (self.width - 1 - y, x),
+            Rotation::Deg180 => (self.width - 1 - x, self.height - 1 - y),
+            Rotation::Deg270 => (y, self.height - 1 - x),
+        };
+
+        let x = if self.mirror_horizontal {
+            self.width - 1 - x
+        } else {
+            x
+        };
+        let y = if self.mirror_vertical {
+            self.height - 1 - y
+        } else {
+            y
+        };
+
+        (x, y)
+    }
+
+    /// Width of the drawable area once rotation and margins are taken into account
+    pub fn inset_width(&self) -> usize {
+        let (width, _) = self.logical_size();
+        width.saturating_sub(self.margins.left + self.margins.right)
+    }
+
+    /// Height of the drawable area once rotation and margins are taken into account
+    pub fn inset_height(&self) -> usize {
+        let (_, height) = self.logical_size();
+        height.saturating_sub(self.margins.top + self.margins.bottom)
+    }
+
+    /// Reads back a pixel in the same coordinates as `set_pixel`, out of range pixels read as off
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        if x >= self.inset_width() || y >= self.inset_height() {
+            return false;
+        }
+
+        let (x, y) = self.to_physical(x + self.margins.left, y + self.margins.top);
+
+        self.raw_pixel(x, y)
+    }
+
+    /// Underlying function for drawing to the canvas, if provided coordinates are out of range,
+    /// this function will fail silently
+    ///
+    /// # Arguments
+    /// * `x` - The x coordinate of the pixel to set
+    /// * `y` - The y coordinate of the pixel to set
+    /// * `enabled` - Whether to set the pixel to an enabled or disabled state (on/off)
+    pub fn set_pixel(&mut self, x: isize, y: isize, enabled: bool) {
+        if x >= self.inset_width() as isize || y >= self.inset_height() as isize || x < 0 || y < 0 {
+            // If a pixel is rendered outside of the canvas, fail silently
+            return;
+        }
+
+        let (x, y) = self.to_physical(
+            x as usize + self.margins.left,
+            y as usize + self.margins.top,
+        );
+        let (target_byte, target_bit) = self.bit_position(x, y);
+
+        self.data[target_byte] = set_bit_at_index(self.data[target_byte], target_bit, enabled);
+    }
+
+    /// Like `set_pixel`, but `DrawMode::Xor` flips whatever is already there, so overlays such as
+    /// cursors can be drawn and erased again without disturbing what's underneath
+    pub fn set_pixel_mode(&mut self, x: isize, y: isize, mode: DrawMode) {
+        let enabled = match mode {
+            DrawMode::Set => true,
+            DrawMode::Clear => false,
+            DrawMode::Xor => {
+                if x >= self.inset_width() as isize
+                    || y >= self.inset_height() as isize
+                    || x < 0
+                    || y < 0
+                {
+                    return;
+                }
+
+                !self.get_pixel(x as usize, y as usize)
+            }
+        };
+
+        self.set_pixel(x, y, enabled);
+    }
+
+    /// Reads a pixel by its position on the panel, ignoring margins and rotation
+    fn raw_pixel(&self, x: usize, y: usize) -> bool {
+        let (byte_index, bit_index) = self.bit_position(x, y);
+
+        get_bit_at_index(self.data[byte_index], bit_index)
+    }
+
+    /// Writes the whole framebuffer to `path` as a `width`×`height` black and white PNG, with
+    /// lit pixels in white. Handy for previewing layouts without the keyboard.
+    pub fn save_png(&self, path: &str) -> Result<(), ScreenError> {
+        let image = image::GrayImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let lit = self.raw_pixel(x as usize, y as usize);
+            image::Luma([if lit { 255 } else { 0 }])
+        });
+
+        image.save_with_format(path, image::ImageFormat::Png)?;
+
+        Ok(())
+    }
+
+    /// The whole framebuffer as a plain (`P1`) PBM image, lit pixels black
+    pub fn to_pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+
+        for y in 0..self.height {
+            let row: Vec<char> = (0..self.width)
+                .map(|x| if self.raw_pixel(x, y) { '1' } else { '0' })
+                .collect();
+
+            // PBM asks for lines of at most 70 characters
+            for line in row.chunks(70) {
+                pbm.extend(line);
+                pbm.push('\n');
+            }
+        }
+
+        pbm
+    }
+
+    /// Writes the whole framebuffer to `path` as an XBM image, lit pixels set
+    pub fn save_xbm(&self, path: &str) -> Result<(), ScreenError> {
+        let stride = self.width.div_ceil(8);
+        let bytes = (0..self.height).flat_map(|y| {
+            (0..stride).map(move |column| {
+                // XBM puts the leftmost pixel of each byte in the least significant bit
+                (0..8)
+                    .filter(|bit| {
+                        column * 8 + bit < self.width && self.raw_pixel(column * 8 + bit, y)
+                    })
+                    .fold(0u8, |byte, bit| byte | 1 << bit)
+            })
+        });
+
+        let mut xbm = format!(
+            "#define kb_width {}\n#define kb_height {}\nstatic unsigned char kb_bits[] = {{\n",
+            self.width, self.height
+        );
+        for line in &bytes.chunks(12) {
+            let line = line.map(|byte| format!("0x{byte:02x}")).join(", ");
+            xbm.push_str(&format!("    {line},\n"));
+        }
+        xbm.push_str("};\n");
+
+        fs::write(path, xbm)?;
+
+        Ok(())
+    }
+
+    /// Byte index and bit index within that byte holding the pixel at physical (`x`, `y`),
+    /// following the layout documented on `data`
+    fn bit_position(&self, x: usize, y: usize) -> (usize, u8) {
+        ((x / 8) * self.height + y, 7 - (x % 8) as u8)
+    }
+}
+
+/// An off-screen monochrome bitmap, used to keep a rendered widget around between frames
+#[derive(Clone, PartialEq)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.pixels[x + y * self.width]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, enabled: bool) {
+        self.pixels[x + y * self.width] = enabled;
+    }
+}
+
+/// Renders a widget once and replays the result each frame until the inputs it was
+/// rendered from (`key`) change or the cache is invalidated.
+pub struct CachedCanvas<K> {
+    x: isize,
+    y: isize,
+    width: usize,
+    height: usize,
+    key: Option<K>,
+    canvas: Option<Canvas>,
+}
+
+impl<K: PartialEq> CachedCanvas<K> {
+    pub fn new(x: isize, y: isize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            key: None,
+            canvas: None,
+        }
+    }
+
+    /// Blits the cached canvas, first calling `render` to draw the widget straight onto the
+    /// screen and capturing the result if nothing is cached for `key` yet
+    pub fn draw(&mut self, screen: &mut Screen, key: K, render: impl FnOnce(&mut Screen)) {
+        if self.key.as_ref() != Some(&key) {
+            self.canvas = None;
+        }
+
+        match &self.canvas {
+            Some(canvas) => screen.blit_canvas(canvas, self.x, self.y),
+            None => {
+                render(screen);
+                self.canvas = Some(screen.capture(self.x, self.y, self.width, self.height));
+                self.key = Some(key);
+            }
+        }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.canvas = None;
+    }
+
+    pub fn is_cached(&self) -> bool {
+        self.canvas.is_some()
+    }
+}
+
+/// Reveals a string one character at a time, for boot messages and the like
+pub struct Typewriter {
+    text: String,
+    revealed: usize,
+}
+
+impl Typewriter {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            revealed: 0,
+        }
+    }
+
+    /// Reveals one more character. Once everything is shown this does nothing.
+    pub fn advance(&mut self) {
+        if !self.is_done() {
+            self.revealed += 1;
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.revealed >= self.text.chars().count()
+    }
+
+    /// The part of the string revealed so far
+    pub fn visible(&self) -> &str {
+        match self.text.char_indices().nth(self.revealed) {
+            Some((end, _)) => &self.text[..end],
+            None => &self.text,
+        }
+    }
+
+    pub fn render(
+        &self,
+        screen: &mut Screen,
+        x: isize,
+        y: isize,
+        size: f32,
+        font: Option<&str>,
+        weight: FontWeight,
+    ) -> Result<(), ScreenError> {
+        screen.draw_text(
+            self.visible(),
+            x,
+            y,
+            size,
+            font,
+            default_spacing(size),
+            weight,
+        )
+    }
+}
+
+/// Horizontally scrolling text for strings too long to fit, such as a now-playing track.
+/// Each `step` draws the current frame and moves the text along by `speed` pixels, with
+/// `gap` pixels of blank space between the end of the text and its next repetition.
+pub struct Marquee {
+    text: String,
+    size: f32,
+    offset: isize,
+    gap: isize,
+    speed: isize,
+}
+
+impl Marquee {
+    pub fn new(text: impl Into<String>, size: f32, gap: isize) -> Self {
+        Self {
+            text: text.into(),
+            size,
+            offset: 0,
+            gap,
+            speed: 1,
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: isize) {
+        self.speed = speed;
+    }
+
+    pub fn step(&mut self, screen: &mut Screen, y: isize) -> Result<(), ScreenError> {
+        let font = screen.font(None)?;
+        let spacing = default_spacing(self.size);
+        let width = screen.measure_text(&self.text, self.size, &font, spacing) as isize;
+        let period = (width + self.gap).max(1);
+
+        // Draw as many repetitions as it takes to cover the visible area
+        let mut x = -self.offset;
+        while x < screen.inset_height() as isize {
+            screen.draw_text(
+                &self.text,
+                x,
+                y,
+                self.size,
+                None,
+                spacing,
+                FontWeight::Normal,
+            )?;
+            x += period;
+        }
+
+        self.offset = (self.offset + self.speed).rem_euclid(period);
+
+        Ok(())
+    }
+}
+
+/// A small activity indicator: a ring of dots with a lit tail chasing around it
+pub struct Spinner {
+    x: isize,
+    y: isize,
+    radius: f32,
+    dots: usize,
+    tail: usize,
+}
+
+impl Spinner {
+    pub fn new(x: isize, y: isize, radius: f32) -> Self {
+        Self {
+            x,
+            y,
+            radius,
+            dots: 8,
+            tail: 3,
+        }
+    }
+
+    /// Draws the spinner for the given frame. The pattern repeats every `dots` frames.
+    pub fn draw(&self, screen: &mut Screen, frame: usize) {
+        let head = frame % self.dots;
+
+        for dot in 0..self.dots {
+            let angle = dot as f32 / self.dots as f32 * std::f32::consts::TAU;
+            let dx = screen.scale_x((angle.cos() * self.radius).round() as isize);
+            let dy = (angle.sin() * self.radius).round() as isize;
+
+            // How many steps this dot trails behind the head
+            let behind = (head + self.dots - dot) % self.dots;
+            screen.set_pixel(self.x + dx, self.y + dy, behind < self.tail);
+        }
+    }
+}
+
+/// A rolling graph of the last `capacity` samples, e.g. CPU usage over time
+pub struct Sparkline {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    max: f32,
+}
+
+impl Sparkline {
+    /// `max` is the sample value that fills the full height of the graph
+    pub fn new(capacity: usize, max: f32) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            max,
+        }
+    }
+
+    /// Records a sample, dropping the oldest once `capacity` is reached
+    pub fn push(&mut self, value: f32) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    /// Draws one column per sample inside the `w` x `h` box at (`x`, `y`), newest at the
+    /// high-x edge, each rising from the bottom in proportion to its value. Until the buffer
+    /// fills, the columns on the low-x side stay empty.
+    pub fn draw(&self, screen: &mut Screen, x: isize, y: isize, w: isize, h: isize) {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+
+        screen.paint_region(x, y, x + w, y + h, false);
+
+        let shown = self.samples.len().min(w as usize);
+        let start_x = x + w - shown as isize;
+
+        for (column, value) in self
+            .samples
+            .iter()
+            .skip(self.samples.len() - shown)
+            .enumerate()
+        {
+            let fraction = (value / self.max).clamp(0.0, 1.0);
+            let height = (fraction * h as f32).round() as isize;
+            if height == 0 {
+                continue;
+            }
+
+            let column_x = start_x + column as isize;
+            screen.draw_line(column_x, y + h - height, column_x, y + h - 1, true);
+        }
+    }
+}
+
+#[cfg(feature = "gif")]
+struct GifFrame {
+    pixels: Vec<bool>,
+    delay: Duration,
+}
+
+/// Playback state for an animated GIF. The caller drives it from the render loop by calling
+/// `advance` with the time since the last frame and then `draw`.
+#[cfg(feature = "gif")]
+pub struct GifPlayer {
+    x: isize,
+    y: isize,
+    width: usize,
+    height: usize,
+    frames: Vec<GifFrame>,
+    current: usize,
+    elapsed: Duration,
+}
+
+#[cfg(feature = "gif")]
+impl GifPlayer {
+    // Browsers treat very short delays as "as fast as possible"; clamp so playback is sane
+    const MIN_DELAY: Duration = Duration::from_millis(20);
+
+    pub fn open(path: &str, x: isize, y: isize, threshold: u8) -> Result<Self, gif::DecodingError> {
+        let file = fs::File::open(path)?;
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(file)?;
+
+        let width = decoder.width() as usize;
+        let height = decoder.height() as usize;
+
+        // Frames only cover the region that changed, so composite them onto a full canvas
+        let mut canvas = vec![0_u8; width * height * 4];
+        let mut frames = Vec::new();
+
+        while let Some(frame) = decoder.read_next_frame()? {
+            let previous = canvas.clone();
+            let (left, top) = (frame.left as usize, frame.top as usize);
+
+            for (index, pixel) in frame.buffer.chunks_exact(4).enumerate() {
+                let px = left + index % frame.width as usize;
+                let py = top + index / frame.width as usize;
+
+                if pixel[3] == 0 || px >= width || py >= height {
+                    continue;
+                }
+
+                let offset = (px + py * width) * 4;
+                canvas[offset..offset + 4].copy_from_slice(pixel);
+            }
+
+            frames.push(GifFrame {
+                pixels: canvas
+                    .chunks_exact(4)
+                    .map(|pixel| {
+                        pixel[3] != 0 && luminance(pixel[0], pixel[1], pixel[2]) > threshold
+                    })
+                    .collect(),
+                delay: Duration::from_millis(frame.delay as u64 * 10).max(Self::MIN_DELAY),
+            });
+
+            match frame.dispose {
+                gif::DisposalMethod::Background => {
+                    for py in top..(top + frame.height as usize).min(height) {
+                        for px in left..(left + frame.width as usize).min(width) {
+                            let offset = (px + py * width) * 4;
+                            canvas[offset..offset + 4].fill(0);
+                        }
+                    }
+                }
+                gif::DisposalMethod::Previous => canvas = previous,
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+            frames,
+            current: 0,
+            elapsed: Duration::ZERO,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn delays(&self) -> Vec<Duration> {
+        self.frames.iter().map(|frame| frame.delay).collect()
+    }
+
+    pub fn current_frame(&self) -> usize {
+        self.current
+    }
+
+    /// Moves playback forward by `elapsed`, looping back to the first frame after the last
+    pub fn advance(&mut self, elapsed: Duration) {
+        if self.frames.is_empty() {
+            return;
+        }
+
+        self.elapsed += elapsed;
+
+        while self.elapsed >= self.frames[self.current].delay {
+            self.elapsed -= self.frames[self.current].delay;
+            self.current = (self.current + 1) % self.frames.len();
+        }
+    }
+
+    pub fn draw(&self, screen: &mut Screen) {
+        let Some(frame) = self.frames.get(self.current) else {
+            return;
+        };
+
+        for (index, enabled) in frame.pixels.iter().enumerate() {
+            let px = (index % self.width) as isize;
+            let py = (index / self.width) as isize;
+            screen.set_pixel(self.x + px, self.y + py, *enabled);
+        }
+
+        debug_assert_eq!(frame.pixels.len(), self.width * self.height);
+    }
+}
+
+#[cfg(feature = "gif")]
+fn luminance(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as u8
+}
+
+/// Reduces a row-major grayscale image to on/off pixels by comparing each against a tiled 4×4
+/// Bayer matrix of thresholds
+fn bayer4x4(luma: &[u8], width: usize, height: usize) -> Vec<bool> {
+    const MATRIX: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+    (0..width * height)
+        .map(|index| {
+            let (x, y) = (index % width, index / width);
+            let threshold = (MATRIX[y % 4][x % 4] as f32 + 0.5) * 16.0;
+            luma[index] as f32 > threshold
+        })
+        .collect()
+}
+
+/// Reduces a row-major grayscale image to on/off pixels, diffusing each pixel's quantisation
+/// error onto its unvisited neighbours
+fn floyd_steinberg(luma: &[u8], width: usize, height: usize) -> Vec<bool> {
+    let mut values: Vec<f32> = luma.iter().map(|value| *value as f32).collect();
+    let mut lit = vec![false; width * height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let index = x + y * width;
+            let on = values[index] >= 128.0;
+            let error = values[index] - if on { 255.0 } else { 0.0 };
+            lit[index] = on;
+
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx >= 0 && (nx as usize) < width && ny < height {
+                    values[nx as usize + ny * width] += error * weight;
+                }
+            };
+
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    lit
+}
+
+/// Pen offset of each character of `text` from the start of the line, and the offset the pen
+/// ends up at. Glyphs advance by their advance width plus `spacing`, kerned against the
+/// previous character.
+fn layout_line(
+    font: &Font,
+    fallback: Option<&Font>,
+    text: &str,
+    size: f32,
+    spacing: isize,
+) -> (Vec<f32>, f32) {
+    // Track the pen position fractionally so rounding doesn't accumulate across glyphs
+    let mut pen = 0.0;
+    let mut previous = None;
+    let mut pens = Vec::with_capacity(text.len());
+
+    for letter in text.chars() {
+        if let Some(previous) = previous {
+            pen += font.horizontal_kern(previous, letter, size).unwrap_or(0.0);
+        }
+
+        pens.push(pen);
+
+        // `spacing` is extra tracking on top of the font's own advance
+        let advance = match glyph_font(font, fallback, letter) {
+            Some(glyph_font) => glyph_font.metrics(letter, size).advance_width,
+            None => placeholder_width(font, size) as f32 + 1.0,
+        };
+        pen += advance + spacing as f32;
+        previous = Some(letter);
+    }
+
+    (pens, pen)
+}
+
+/// The font that should draw `letter`: `font` itself when it has the glyph, otherwise
+/// `fallback` if that does. `None` means neither has it and a placeholder is drawn instead.
+fn glyph_font<'a>(font: &'a Font, fallback: Option<&'a Font>, letter: char) -> Option<&'a Font> {
+    // Whitespace legitimately has no outline, so only printable characters need a glyph
+    if letter.is_whitespace() || letter.is_control() || font.lookup_glyph_index(letter) != 0 {
+        return Some(font);
+    }
+
+    fallback.filter(|fallback| fallback.lookup_glyph_index(letter) != 0)
+}
+
+/// Width of the box drawn for a missing glyph, about that of a capital letter
+fn placeholder_width(font: &Font, size: f32) -> isize {
+    (cap_height(font, size) * 2 / 3).max(1)
+}
+
+/// Height of a capital letter above the baseline, used as the reference for placing glyphs
+fn cap_height(font: &Font, size: f32) -> isize {
+    if font.lookup_glyph_index('H') != 0 {
+        let metrics = font.metrics('H', size);
+        metrics.ymin as isize + metrics.height as isize
+    } else {
+        font.horizontal_line_metrics(size)
+            .map_or(size, |metrics| metrics.ascent)
+            .round() as isize
+    }
+}
+
+/// Tracking used between glyphs when no explicit spacing is given
+fn default_spacing(size: f32) -> isize {
+    size as isize / 24
+}
+
+/// Thickens a one-byte-per-pixel glyph bitmap by OR-ing each pixel with its left neighbour,
+/// growing it by one column
+fn embolden(bitmap: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut bold = Vec::with_capacity((width + 1) * height);
+
+    for row in bitmap.chunks(width.max(1)).take(height) {
+        for x in 0..=width {
+            let current = row.get(x).copied().unwrap_or(0);
+            let left = if x > 0 { row[x - 1] } else { 0 };
+            bold.push(current.max(left));
+        }
+    }
+
+    bold
+}
+
+pub fn get_bit_at_index(byte: u8, bit_index: u8) -> bool {
+    let mask = 0b10000000 >> bit_index;
+
+    mask & byte != 0
+}
+
+pub fn set_bit_at_index(byte: u8, bit_index: u8, enabled: bool) -> u8 {
+    let mask = 0b10000000 >> bit_index;
+
+    if enabled {
+        mask | byte
+    } else {
+        (mask ^ 0b11111111) & byte
+    }
+}
+
+/// Paces a loop to a fixed interval, sleeping only for the part of it the iteration didn't use
+pub struct FrameLimiter {
+    interval: Duration,
+    next: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Instant::now() + interval,
+        }
+    }
+
+    /// Fails unless `fps` is a positive, finite rate whose interval a `Duration` can hold
+    pub fn from_fps(fps: f32) -> Result<Self, String> {
+        if !fps.is_finite() || fps <= 0.0 {
+            return Err(format!("invalid frame rate {fps}"));
+        }
+
+        let interval = Duration::try_from_secs_f32(1.0 / fps)
+            .map_err(|e| format!("invalid frame rate {fps}: {e}"))?;
+
+        Ok(Self::new(interval))
+    }
+
+    /// Sleeps until the next frame is due. A frame that overran restarts the schedule from now
+    /// instead of rushing to catch up.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+
+        match self.next.checked_duration_since(now) {
+            Some(remaining) => {
+                thread::sleep(remaining);
+                self.next += self.interval;
+            }
+            None => self.next = now + self.interval,
+        }
+    }
+}
+
+/// Command line options. Defaults match the stock 62x128 panel.
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Draws a clock and system stats on the keyboard's display"
+)]
+struct Args {
+    /// Panel width in pixels
+    #[arg(long, default_value_t = 62)]
+    width: usize,
+
+    /// Panel height in pixels
+    #[arg(long, default_value_t = 128)]
+    height: usize,
+
+    /// TTF/OTF font to draw with instead of the embedded one
+    #[arg(long, value_name = "PATH")]
+    font: Option<String>,
+
+    /// Font size of the clock
+    #[arg(long, default_value_t = 64.0)]
+    clock_size: f64,
+
+    /// chrono strftime format for the clock, e.g. "%H:%M" for 24 hour time
+    #[arg(long, default_value = DEFAULT_TIME_FORMAT)]
+    time_format: String,
+
+    /// TOML file describing what to draw where. Falls back to the default layout if it
+    /// can't be read or parsed, and is ignored when `KB_GUI_WIDGETS` is set.
+    #[arg(long, value_name = "PATH")]
+    layout: Option<String>,
+
+    /// Print a single frame to stdout instead of driving a keyboard
+    #[arg(long)]
+    preview: bool,
+
+    /// Light the background and leave the drawing dark
+    #[arg(long)]
+    invert: bool,
+
+    /// Mount point reported by the `disk` widget
+    #[arg(long, default_value = "/")]
+    disk_mount: String,
+
+    /// Milliseconds between frames
+    #[arg(long, default_value_t = 200)]
+    refresh_ms: u64,
+
+    /// Frames per second, instead of `--refresh-ms`
+    #[arg(long, conflicts_with = "refresh_ms")]
+    fps: Option<f32>,
+
+    /// Milliseconds between CPU and memory samples. Values below sysinfo's minimum CPU update
+    /// interval are raised to it.
+    #[arg(long, default_value_t = 1000)]
+    stats_ms: u64,
+}
+
+/// Environment variable listing the widgets to show, e.g. `KB_GUI_WIDGETS=clock,cpu,mem`
+const WIDGETS_ENV: &str = "KB_GUI_WIDGETS";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Widget {
+    Clock,
+    Cpu,
+    Mem,
+    Disk,
+    Net,
+    Temp,
+    Cores,
+    Battery,
+}
+
+impl Widget {
+    /// Shown when neither `KB_GUI_WIDGETS` nor a layout file picks the widgets
+    pub const DEFAULT: [Widget; 3] = [Widget::Clock, Widget::Cpu, Widget::Mem];
+}
+
+impl FromStr for Widget {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "clock" => Ok(Widget::Clock),
+            "cpu" => Ok(Widget::Cpu),
+            "mem" => Ok(Widget::Mem),
+            "disk" => Ok(Widget::Disk),
+            "net" => Ok(Widget::Net),
+            "temp" => Ok(Widget::Temp),
+            "cores" => Ok(Widget::Cores),
+            "battery" => Ok(Widget::Battery),
+            _ => Err(format!(
+                "unknown widget `{name}` (expected one of: clock, cpu, mem, disk, net, temp, cores, battery)"
+            )),
+        }
+    }
+}
+
+/// Parses a comma separated widget list, ignoring surrounding whitespace and empty entries
+pub fn parse_widgets(list: &str) -> Result<Vec<Widget>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(Widget::from_str)
+        .collect()
+}
+
+/// Decides what gets drawn from the loaded layout and the `KB_GUI_WIDGETS` list. The list
+/// overrides the layout file, which is then set aside for the default arrangement of the
+/// listed widgets. With neither, that arrangement shows `Widget::DEFAULT`.
+fn select_widgets(
+    layout: Option<Layout>,
+    env_list: Option<&str>,
+) -> Result<(Option<Layout>, Vec<Widget>), String> {
+    match (env_list, layout) {
+        (Some(list), layout) => {
+            let widgets = parse_widgets(list).map_err(|e| format!("{WIDGETS_ENV}: {e}"))?;
+            if layout.is_some() {
+                warn!("{WIDGETS_ENV} is set, ignoring the layout file");
+            }
+            Ok((None, widgets))
+        }
+        (None, Some(layout)) => {
+            let widgets = layout.widgets();
+            Ok((Some(layout), widgets))
+        }
+        (None, None) => Ok((None, Widget::DEFAULT.to_vec())),
+    }
+}
+
+/// Environment variables overriding the matching `DeviceFilter` fields, as hex like `0x4B42`
+const VENDOR_ID_ENV: &str = "KB_GUI_VENDOR_ID";
+const PRODUCT_ID_ENV: &str = "KB_GUI_PRODUCT_ID";
+const USAGE_PAGE_ENV: &str = "KB_GUI_USAGE_PAGE";
+
+/// Parses a 16 bit id written in hex, with or without a `0x` prefix
+fn parse_hex_u16(value: &str) -> Result<u16, String> {
+    let digits = value.trim();
+    let digits = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+        .unwrap_or(digits);
+
+    u16::from_str_radix(digits, 16).map_err(|e| format!("`{value}` is not a 16 bit hex id: {e}"))
+}
+
+/// The default `DeviceFilter` with any fields set through the environment replaced
+fn device_filter_from_env() -> Result<DeviceFilter, String> {
+    let mut filter = DeviceFilter::default();
+
+    for (name, field) in [
+        (VENDOR_ID_ENV, &mut filter.vendor_id),
+        (PRODUCT_ID_ENV, &mut filter.product_id),
+        (USAGE_PAGE_ENV, &mut filter.usage_page),
+    ] {
+        if let Ok(value) = env::var(name) {
+            *field = parse_hex_u16(&value).map_err(|e| format!("{name}: {e}"))?;
+        }
+    }
+
+    Ok(filter)
+}
+
+/// Serial number of the keyboard to use when more than one matching device is connected
+const SERIAL_ENV: &str = "KB_GUI_SERIAL";
+
+/// Top level failures, each carrying enough context to tell the user what went wrong
+pub enum AppError {
+    Hid {
+        context: &'static str,
+        source: HidError,
+    },
+    DeviceNotFound,
+    Screen(ScreenError),
+    Config(String),
+    Signal(ctrlc::Error),
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Hid { context, source } => write!(f, "{context}: {source}"),
+            AppError::DeviceNotFound => f.write_str("failed to find device"),
+            AppError::Screen(e) => write!(f, "failed to draw: {e}"),
+            AppError::Config(e) => write!(f, "invalid configuration: {e}"),
+            AppError::Signal(e) => write!(f, "failed to install signal handler: {e}"),
+        }
+    }
+}
+
+// `main` reports errors through Debug, so make that the readable message too
+impl std::fmt::Debug for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<ScreenError> for AppError {
+    fn from(e: ScreenError) -> Self {
+        AppError::Screen(e)
+    }
+}
+
+/// Adds context to a `HidError` as it is converted into an `AppError`
+fn hid_context(context: &'static str) -> impl FnOnce(HidError) -> AppError {
+    move |source| AppError::Hid { context, source }
+}
+
+fn main() -> Result<(), AppError> {
+    env_logger::init();
+
+    let args = Args::parse();
+
+    // A missing or malformed layout file shouldn't leave the panel blank
+    let layout = args
+        .layout
+        .as_deref()
+        .and_then(|path| match Layout::load(path) {
+            Ok(layout) => Some(layout),
+            Err(e) => {
+                warn!("ignoring layout file, using the default layout: {e}");
+                None
+            }
+        });
+
+    let (layout, widgets) =
+        select_widgets(layout, env::var(WIDGETS_ENV).ok().as_deref()).map_err(AppError::Config)?;
+
+    let mut sys = System::new_all();
+
+    if args.preview {
+        let mut screen = Screen::from_device(NullAdapter, args.width, args.height)
+            .map_err(|e| AppError::Config(e.to_string()))?;
+
+        if let Some(font) = &args.font {
+            screen.load_font(font)?;
+        }
+
+        let (layout, widgets) = check_layout(layout, widgets, &screen);
+
+        // CPU usage is measured between two refreshes
+        thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+        let stats = Stats::sample(&mut sys, &mut NetRate::new(), &args, &widgets);
+
+        draw_frame(&mut screen, &args, layout.as_ref(), &widgets, &stats)?;
+        println!("{screen}");
+
+        return Ok(());
+    }
+
+    let mut api = HidApi::new().map_err(hid_context("failed to initialize HID API"))?;
+
+    let filter = device_filter_from_env().map_err(AppError::Config)?;
+
+    let device = match env::var(SERIAL_ENV) {
+        Ok(serial) => find_device_by_serial(&api, &filter, &serial),
+        Err(_) => find_device(&api, &filter),
+    }
+    .ok_or(AppError::DeviceNotFound)?
+    .open_device(&api)
+    .map_err(hid_context("failed to open device"))?;
+    info!("opened device");
+
+    let mut screen = Screen::from_device(device, args.width, args.height)
+        .map_err(|e| AppError::Config(e.to_string()))?;
+    screen.set_device_filter(filter);
+    screen.set_send_policy(SendPolicy {
+        retries: 2,
+        ..SendPolicy::default()
+    });
+    screen.set_invert_output(args.invert);
+
+    if let Some(font) = &args.font {
+        screen.load_font(font)?;
+    }
+
+    let (layout, widgets) = check_layout(layout, widgets, &screen);
+
+    let mut limiter = match args.fps {
+        Some(fps) => FrameLimiter::from_fps(fps).map_err(AppError::Config)?,
+        None => FrameLimiter::new(Duration::from_millis(args.refresh_ms)),
+    };
+    let stats_interval =
+        Duration::from_millis(args.stats_ms).max(System::MINIMUM_CPU_UPDATE_INTERVAL);
+    let mut next_sample = Instant::now();
+    let mut stats = Stats::default();
+    let mut net = NetRate::new();
+
+    // Stop after the current frame on SIGINT/SIGTERM so the panel can be blanked. A second
+    // signal exits straight away, e.g. while stuck waiting for the keyboard to reconnect.
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_running = running.clone();
+    ctrlc::set_handler(move || {
+        if !handler_running.swap(false, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })
+    .map_err(AppError::Signal)?;
+
+    while running.load(Ordering::SeqCst) {
+        if Instant::now() >= next_sample {
+            stats = Stats::sample(&mut sys, &mut net, &args, &widgets);
+            next_sample = Instant::now() + stats_interval;
+        }
+
+        draw_frame(&mut screen, &args, layout.as_ref(), &widgets, &stats)?;
+
+        screen.present_or_reconnect(&mut api)?;
+        limiter.wait();
+    }
+
+    // Don't leave the last frame burned into the OLED
+    screen.clear();
+    screen.present()?;
+
+    Ok(())
+}
+
+/// Keeps `layout` and the widgets it picked if it fits `screen`. Otherwise warns and falls back
+/// to the default layout, like an unreadable layout file does.
+fn check_layout(
+    layout: Option<Layout>,
+    widgets: Vec<Widget>,
+    screen: &Screen,
+) -> (Option<Layout>, Vec<Widget>) {
+    if let Some(Err(e)) = layout.as_ref().map(|layout| layout.validate(screen)) {
+        warn!("ignoring layout file, using the default layout: {e}");
+        return (None, Widget::DEFAULT.to_vec());
+    }
+
+    (layout, widgets)
+}
+
+/// Composes one frame into `screen`, following `layout` when one was loaded and otherwise the
+/// default arrangement of the selected widgets
+fn draw_frame(
+    screen: &mut Screen,
+    args: &Args,
+    layout: Option<&Layout>,
+    widgets: &[Widget],
+    stats: &Stats,
+) -> Result<(), ScreenError> {
+    if let Some(layout) = layout {
+        return layout.draw(screen, args, stats);
+    }
+
+    // clear bg
+    screen.clear();
+
+    if widgets.contains(&Widget::Clock) {
+        screen.draw_time(
+            &SystemClock,
+            &args.time_format,
+            args.clock_size,
+            args.font.clone(),
+            FontWeight::Normal,
+        )?;
+    }
+
+    // screen.draw_text("CPU:", 10, 10, 32.0, None);
+    // screen.draw_text(
+    //     &format!("{:.2}%", cpu_usage).to_string(),
+    //     40,
+    //     10,
+    //     32.0,
+    //     None,
+    // );
+    //
+    // screen.draw_text("MEM:", 10, 24, 32.0, None);
+    // screen.draw_text(
+    //     &format!(
+    //         "{:.2}/{:.2}GB",
+    //         bytes_to_gb(current_ram),
+    //         bytes_to_gb(total_ram)
+    //     )
+    //     .to_string(),
+    //     40,
+    //     24,
+    //     32.0,
+    //     None,
+    // );
+
+    // Each readout gets a line of its own in the band below the clock, all at the largest size
+    // that fits every one of them, and clear of the core bars along the far end
+    let readouts: Vec<String> = widgets
+        .iter()
+        .filter_map(|widget| widget_text(*widget, stats, args))
+        .collect();
+
+    if !readouts.is_empty() {
+        let font = screen.font(args.font.as_deref())?;
+        let top = if widgets.contains(&Widget::Clock) {
+            10 + cap_height(&font, args.clock_size as f32) + 4
+        } else {
+            2
+        };
+        let pitch = (screen.inset_width() as isize - top) / readouts.len() as isize;
+        let max_width = if widgets.contains(&Widget::Cores) {
+            screen.inset_height().saturating_sub(16)
+        } else {
+            screen.inset_height()
+        };
+
+        let size = readouts
+            .iter()
+            .map(|text| screen.fit_text(text, max_width, pitch - 2, 32.0, &font))
+            .fold(32.0, f32::min);
+
+        for (index, text) in readouts.into_iter().enumerate() {
+            let y = (top + index as isize * pitch).max(0) as usize;
+            screen.render_centered(
+                text,
+                size as f64,
+                y,
+                args.font.as_deref(),
+                FontWeight::Normal,
+            )?;
+        }
+    }
+
+    if widgets.contains(&Widget::Cores) {
+        let (width, height) = (
+            screen.inset_width() as isize,
+            screen.inset_height() as isize,
+        );
+        screen.draw_core_bars(&stats.core_usage, 0, height - 8, width, 8);
+    }
+
+    // Tucked into the corner above the clock, clear of the core bars, and skipped entirely
+    // without a battery
+    if let (true, Some(battery)) = (widgets.contains(&Widget::Battery), stats.battery) {
+        screen.draw_battery(1, 1, 7, 16, battery.percent / 100.0, battery.charging);
+    }
+
+    Ok(())
+}
+
+/// The readout shown for a stats widget, or `None` for the clock and core bars, which are
+/// drawn separately, and for the battery on machines without one
+fn widget_text(widget: Widget, stats: &Stats, args: &Args) -> Option<String> {
+    match widget {
+        Widget::Clock | Widget::Cores => None,
+        Widget::Cpu => Some(format!("C    {:.1}%", stats.cpu_usage)),
+        Widget::Mem => Some(format!("M    {:.1} G", bytes_to_gb(stats.used_memory))),
+        Widget::Disk => Some(match stats.disk {
+            Some((used, total)) => {
+                format!("D    {:.0}/{:.0} G", bytes_to_gb(used), bytes_to_gb(total))
+            }
+            None => format!("D    {} ?", args.disk_mount),
+        }),
+        Widget::Net => {
+            let (rx, tx) = stats.net;
+            Some(format!("N    {}/{}", format_rate(rx), format_rate(tx)))
+        }
+        Widget::Temp => Some(match stats.cpu_temp {
+            Some(temp) => format!("T    {temp:.0} C"),
+            None => "T    ?".to_string(),
+        }),
+        Widget::Battery => stats.battery.map(|battery| {
+            let charging = if battery.charging { " +" } else { "" };
+            format!("B    {:.0}%{charging}", battery.percent)
+        }),
+    }
+}
+
+/// A display layout read from `--layout`, replacing the default arrangement of widgets:
+///
+/// ```toml
+/// [[element]]
+/// type = "clock"
+/// y = 10
+/// size = 64.0
+///
+/// [[element]]
+/// type = "cpu"
+/// x = 4
+/// y = 42
+///
+/// [[element]]
+/// type = "text"
+/// text = "hello"
+/// y = 52
+/// font = "/usr/share/fonts/TTF/DejaVuSans.ttf"
+/// ```
+///
+/// Positions are in text coordinates, as for `Screen::draw_text`. Elements without an `x` are
+/// centered, `size` defaults to 32 and `font` to `--font`.
+#[derive(Deserialize)]
+struct Layout {
+    #[serde(rename = "element", default)]
+    elements: Vec<LayoutElement>,
+}
+
+#[derive(Deserialize)]
+struct LayoutElement {
+    #[serde(flatten)]
+    kind: ElementKind,
+    x: Option<isize>,
+    y: isize,
+    #[serde(default = "default_element_size")]
+    size: f32,
+    font: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ElementKind {
+    Clock,
+    Cpu,
+    Mem,
+    Disk,
+    Net,
+    Temp,
+    Battery,
+    Text { text: String },
+}
+
+impl ElementKind {
+    fn widget(&self) -> Option<Widget> {
+        match self {
+            ElementKind::Clock => Some(Widget::Clock),
+            ElementKind::Cpu => Some(Widget::Cpu),
+            ElementKind::Mem => Some(Widget::Mem),
+            ElementKind::Disk => Some(Widget::Disk),
+            ElementKind::Net => Some(Widget::Net),
+            ElementKind::Temp => Some(Widget::Temp),
+            ElementKind::Battery => Some(Widget::Battery),
+            ElementKind::Text { .. } => None,
+        }
+    }
+}
+
+fn default_element_size() -> f32 {
+    32.0
+}
+
+impl Layout {
+    fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        toml::from_str(&source).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// The widgets this layout shows, so only their stats get sampled
+    fn widgets(&self) -> Vec<Widget> {
+        self.elements
+            .iter()
+            .filter_map(|element| element.kind.widget())
+            .collect()
+    }
+
+    /// Rejects elements positioned outside the screen's drawable area
+    fn validate(&self, screen: &Screen) -> Result<(), String> {
+        // Text runs along the long edge of the panel, so text x spans the inset height
+        let (max_x, max_y) = (
+            screen.inset_height() as isize,
+            screen.inset_width() as isize,
+        );
+
+        for (index, element) in self.elements.iter().enumerate() {
+            let x = element.x.unwrap_or(0);
+            if !(0..max_x).contains(&x) || !(0..max_y).contains(&element.y) {
+                return Err(format!(
+                    "element {} at ({x}, {}) is outside the {max_x}x{max_y} screen",
+                    index + 1,
+                    element.y
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&self, screen: &mut Screen, args: &Args, stats: &Stats) -> Result<(), ScreenError> {
+        screen.clear();
+
+        for element in &self.elements {
+            let text = match &element.kind {
+                ElementKind::Clock => format_time(&SystemClock, &args.time_format)?,
+                ElementKind::Text { text } => text.clone(),
+                kind => kind
+                    .widget()
+                    .and_then(|widget| widget_text(widget, stats, args))
+                    .unwrap_or_default(),
+            };
+            let font = element.font.as_deref().or(args.font.as_deref());
+
+            match element.x {
+                Some(x) => screen.draw_text(
+                    &text,
+                    x,
+                    element.y,
+                    element.size,
+                    font,
+                    default_spacing(element.size),
+                    FontWeight::Normal,
+                )?,
+                None => screen.draw_text_aligned(
+                    &text,
+                    TextAlign::Center,
+                    element.y,
+                    element.size,
+                    font,
+                    FontWeight::Normal,
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The latest system readings, refreshed every `--stats-ms`
+#[derive(Default)]
+struct Stats {
+    cpu_usage: f32,
+    /// Usage of each logical core, in the order sysinfo lists them
+    core_usage: Vec<f32>,
+    used_memory: u64,
+    /// Used and total bytes on `--disk-mount`
+    disk: Option<(u64, u64)>,
+    /// Received and transmitted bytes per second
+    net: (f64, f64),
+    /// Degrees Celsius, when a CPU sensor is reported
+    cpu_temp: Option<f32>,
+    battery: Option<BatteryStats>,
+}
+
+impl Stats {
+    /// Refreshes what the selected widgets need from `sys` and reads it back
+    fn sample(sys: &mut System, net: &mut NetRate, args: &Args, widgets: &[Widget]) -> Self {
+        sys.refresh_cpu();
+        sys.refresh_memory();
+
+        let disk = if widgets.contains(&Widget::Disk) {
+            sys.refresh_disks();
+            disk_usage(sys, &args.disk_mount)
+        } else {
+            None
+        };
+
+        let net = if widgets.contains(&Widget::Net) {
+            sys.refresh_networks();
+            net.update(sys)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let cpu_temp = if widgets.contains(&Widget::Temp) {
+            sys.refresh_components();
+            cpu_temp(sys)
+        } else {
+            None
+        };
+
+        let battery = if widgets.contains(&Widget::Battery) {
+            BatteryStats::read()
+        } else {
+            None
+        };
+
+        Self {
+            cpu_usage: sys.global_cpu_info().cpu_usage(),
+            core_usage: sys.cpus().iter().map(|cpu| cpu.cpu_usage()).collect(),
+            used_memory: sys.used_memory(),
+            disk,
+            net,
+            cpu_temp,
+            battery,
+        }
+    }
+}
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+/// Charge of the first battery the kernel reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BatteryStats {
+    percent: f32,
+    charging: bool,
+}
+
+impl BatteryStats {
+    /// Reads sysfs, so this is `None` on machines without a battery and anywhere but Linux
+    fn read() -> Option<Self> {
+        let read = |path: PathBuf| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|value| value.trim().to_string())
+        };
+
+        fs::read_dir(POWER_SUPPLY_DIR)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|supply| read(supply.join("type")).as_deref() == Some("Battery"))
+            .find_map(|supply| {
+                let percent = read(supply.join("capacity"))?.parse().ok()?;
+                let charging = read(supply.join("status")).as_deref() == Some("Charging");
+
+                Some(Self { percent, charging })
+            })
+    }
+}
+
+/// Current CPU temperature in degrees Celsius, or `None` when no CPU sensor is reported.
+/// Sensor labels vary by platform and driver, e.g. `coretemp Package id 0` on Intel and
+/// `k10temp Tctl` on AMD.
+fn cpu_temp(sys: &System) -> Option<f32> {
+    const CPU_LABELS: [&str; 4] = ["cpu", "package id", "tctl", "tdie"];
+
+    sys.components()
+        .iter()
+        .find(|component| {
+            let label = component.label().to_lowercase();
+            CPU_LABELS.iter().any(|cpu| label.contains(cpu))
+        })
+        .map(|component| component.temperature())
+        .filter(|temp| temp.is_finite())
+}
+
+/// Turns the running network byte counters into rates by comparing consecutive samples
+#[derive(Default)]
+pub struct NetRate {
+    previous: Option<(u64, u64, Instant)>,
+}
+
+impl NetRate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bytes per second received and transmitted across all interfaces since the last call.
+    /// `sys` should have had its networks refreshed. The first call has nothing to compare
+    /// against and reports zero.
+    pub fn update(&mut self, sys: &System) -> (f64, f64) {
+        let (rx, tx) = sys
+            .networks()
+            .into_iter()
+            .fold((0, 0), |(rx, tx), (_, data)| {
+                (rx + data.total_received(), tx + data.total_transmitted())
+            });
+        let now = Instant::now();
+
+        let rate = match self.previous {
+            Some((prev_rx, prev_tx, at)) => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    (
+                        rx.saturating_sub(prev_rx) as f64 / elapsed,
+                        tx.saturating_sub(prev_tx) as f64 / elapsed,
+                    )
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            None => (0.0, 0.0),
+        };
+
+        self.previous = Some((rx, tx, now));
+        rate
+    }
+}
+
+/// Used and total bytes of the disk mounted at `mount`, or `None` if nothing is mounted there
+fn disk_usage(sys: &System, mount: &str) -> Option<(u64, u64)> {
+    sys.disks()
+        .iter()
+        .find(|disk| disk.mount_point() == std::path::Path::new(mount))
+        .map(|disk| {
+            let total = disk.total_space();
+            (total.saturating_sub(disk.available_space()), total)
+        })
+}
+
+/// A bytes per second rate in the largest unit that keeps it above one, e.g. `12.3K`
+fn format_rate(bytes_per_sec: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "K", "M", "G"];
+
+    let mut rate = bytes_per_sec;
+    let mut unit = 0;
+    while rate >= 1024.0 && unit < UNITS.len() - 1 {
+        rate /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{rate:.1}{}", UNITS[unit])
+}
+
+fn bytes_to_gb(bytes: u64) -> f64 {
+    bytes as f64 / (1 << 30) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHidAdapter;
+
+    fn mock_screen(width: usize, height: usize) -> Screen {
+        Screen::from_device(MockHidAdapter::new(), width, height).unwrap()
+    }
+
+    fn mock(screen: &Screen) -> &MockHidAdapter {
+        screen
+            .device()
+            .as_any()
+            .downcast_ref()
+            .expect("screen should wrap a MockHidAdapter")
+    }
+
+    fn lit_pixels(screen: &Screen) -> usize {
+        screen
+            .data
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    /// Inclusive x and y ranges of the lit pixels, in physical panel coordinates
+    fn lit_bounds(screen: &Screen) -> ((usize, usize), (usize, usize)) {
+        let lit: Vec<(usize, usize)> = (0..screen.width)
+            .cartesian_product(0..screen.height)
+            .filter(|&(x, y)| screen.raw_pixel(x, y))
+            .collect();
+        let x = lit.iter().map(|&(x, _)| x).minmax().into_option();
+        let y = lit.iter().map(|&(_, y)| y).minmax().into_option();
+
+        (x.expect("nothing is lit"), y.expect("nothing is lit"))
+    }
+
+    #[test]
+    fn present_writes_every_packet_then_only_changes() {
+        let mut screen = mock_screen(62, 128);
+        screen.present().unwrap();
+
+        {
+            let written = mock(&screen).written();
+            assert_eq!(written.len(), 35);
+            for (index, bytes) in written.iter().enumerate() {
+                assert_eq!(bytes.len(), PAYLOAD_SIZE);
+                assert_eq!(bytes[..2], [1, index as u8]);
+            }
+        }
+        mock(&screen).clear();
+
+        // Byte (20 / 8) * 128 + 70 = 326 is the 27th byte of packet 10, and x = 20 is bit 4
+        screen.set_pixel(20, 70, true);
+        screen.present().unwrap();
+
+        let written = mock(&screen).written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][1], 10);
+        assert_eq!(written[0][2 + 26], 0b0001_0000);
+    }
+
+    #[test]
+    fn failed_present_resends_the_whole_frame() {
+        let mut screen = mock_screen(62, 128);
+        mock(&screen).fail_next(1);
+        assert!(matches!(
+            screen.present(),
+            Err(ScreenError::Disconnected(_))
+        ));
+        assert!(mock(&screen).written().is_empty());
+
+        mock(&screen).set_short_writes(true);
+        assert!(screen.present().is_err());
+        assert_eq!(mock(&screen).written().len(), 1);
+
+        mock(&screen).set_short_writes(false);
+        mock(&screen).clear();
+        screen.present().unwrap();
+        assert_eq!(mock(&screen).written().len(), 35);
+    }
+
+    #[test]
+    fn get_pixel_reads_back_every_set_pixel() {
+        let rotations = [
+            Rotation::Deg0,
+            Rotation::Deg90,
+            Rotation::Deg180,
+            Rotation::Deg270,
+        ];
+
+        for rotation in rotations {
+            for (horizontal, vertical) in [(false, false), (true, true)] {
+                let mut screen = mock_screen(62, 128);
+                screen.set_rotation(rotation);
+                screen.set_mirror(horizontal, vertical);
+                let (width, height) = (screen.inset_width(), screen.inset_height());
+
+                for y in 0..height {
+                    for x in 0..width {
+                        screen.set_pixel(x as isize, y as isize, true);
+                        assert!(screen.get_pixel(x, y), "({x}, {y}) under {rotation:?}");
+                    }
+                }
+                assert_eq!(lit_pixels(&screen), 62 * 128);
+
+                assert!(!screen.get_pixel(width, 0));
+                assert!(!screen.get_pixel(0, height));
+            }
+        }
+    }
+
+    #[test]
+    fn fit_lines_grows_with_the_line_axis() {
+        let short = mock_screen(30, 128);
+        let tall = mock_screen(62, 128);
+        let font = tall.default_font.clone();
+
+        let short_size = short.fit_lines(3, 0.2, 100.0, 1.0, &font);
+        let tall_size = tall.fit_lines(3, 0.2, 100.0, 1.0, &font);
+        assert!(tall_size > short_size, "{tall_size} <= {short_size}");
+
+        // Lengthening the other axis leaves the size alone
+        let long = mock_screen(30, 256);
+        assert_eq!(long.fit_lines(3, 0.2, 100.0, 1.0, &font), short_size);
+    }
+
+    #[test]
+    fn packet_delay_only_falls_between_writes() {
+        let delay = Duration::from_millis(5);
+        let clock = Rc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut screen = mock_screen(62, 128);
+        screen.set_clock(clock.clone());
+        screen.set_packet_delay(delay);
+
+        screen.present().unwrap();
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + delay * 34);
+
+        // A single changed packet has nothing to wait for
+        screen.set_pixel(0, 0, true);
+        screen.present().unwrap();
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + delay * 34);
+    }
+
+    #[test]
+    fn send_region_delays_only_between_sent_packets() {
+        let delay = Duration::from_millis(5);
+        let clock = Rc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut screen = mock_screen(62, 128);
+        screen.set_clock(clock.clone());
+        screen.set_packet_delay(delay);
+        screen.present().unwrap();
+        let start = clock.now();
+
+        // The region spans many packets, but only the last one it covers has changed
+        screen.set_pixel(0, 127, true);
+        screen.send_region(0, 0, 0, 127).unwrap();
+        assert_eq!(mock(&screen).written().len(), 36);
+        assert_eq!(clock.now(), start);
+    }
+
+    #[test]
+    fn checkbox_draws_a_cross_only_when_checked() {
+        let mut unchecked = mock_screen(62, 128);
+        unchecked.draw_checkbox(10, 10, 12, false);
+        let mut checked = mock_screen(62, 128);
+        checked.draw_checkbox(10, 10, 12, true);
+
+        // Both share the outline, the cross adds two pixels per interior row
+        assert_eq!(lit_pixels(&unchecked), 4 * 11);
+        assert_eq!(lit_pixels(&checked), 4 * 11 + 2 * 8);
+        assert!(!unchecked.get_pixel(12, 12));
+        assert!(checked.get_pixel(12, 12));
+        assert!(checked.get_pixel(19, 12));
+    }
+
+    #[test]
+    fn toggle_knob_moves_to_the_high_end_when_on() {
+        let mut off = mock_screen(62, 128);
+        off.draw_toggle(10, 10, 30, 12, false);
+        let mut on = mock_screen(62, 128);
+        on.draw_toggle(10, 10, 30, 12, true);
+
+        assert!(off.get_pixel(13, 16));
+        assert!(!off.get_pixel(36, 16));
+        assert!(!on.get_pixel(13, 16));
+        assert!(on.get_pixel(36, 16));
+        assert_eq!(lit_pixels(&off), lit_pixels(&on));
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn gif_player_decodes_frames_and_delays() {
+        let path = env::temp_dir().join(format!("kb-test-{}.gif", std::process::id()));
+        {
+            let palette = [0, 0, 0, 255, 255, 255];
+            let file = fs::File::create(&path).unwrap();
+            let mut encoder = gif::Encoder::new(file, 4, 4, &palette).unwrap();
+
+            // A white frame for 50ms, then a black one asking for 10ms
+            for (colour, delay) in [(1, 5), (0, 1)] {
+                let mut frame =
+                    gif::Frame::from_palette_pixels(4, 4, &[colour; 16], &palette, None);
+                frame.delay = delay;
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let mut player = GifPlayer::open(path.to_str().unwrap(), 0, 0, 128).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(player.frame_count(), 2);
+        assert_eq!(
+            player.delays(),
+            [Duration::from_millis(50), GifPlayer::MIN_DELAY]
+        );
+
+        let mut screen = mock_screen(62, 128);
+        player.draw(&mut screen);
+        assert_eq!(lit_pixels(&screen), 16);
+
+        player.advance(Duration::from_millis(50));
+        assert_eq!(player.current_frame(), 1);
+        screen.clear();
+        player.draw(&mut screen);
+        assert_eq!(lit_pixels(&screen), 0);
+    }
+
+    #[test]
+    fn draw_time_renders_the_clock_instant() {
+        // Seconds don't depend on the local time zone
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(45));
+        assert_eq!(format_time(&clock, "%S").unwrap(), "45");
+
+        let mut screen = mock_screen(62, 128);
+        screen
+            .draw_time(&clock, "%S", 24.0, None, FontWeight::Normal)
+            .unwrap();
+        let mut expected = mock_screen(62, 128);
+        expected
+            .draw_text_aligned("45", TextAlign::Center, 10, 24.0, None, FontWeight::Normal)
+            .unwrap();
+        assert!(lit_pixels(&screen) > 0);
+        assert_eq!(screen.data, expected.data);
+
+        clock.advance(Duration::from_secs(1));
+        screen.clear();
+        screen
+            .draw_time(&clock, "%S", 24.0, None, FontWeight::Normal)
+            .unwrap();
+        assert_ne!(screen.data, expected.data);
+    }
+
+    #[test]
+    fn clock_tick_resends_only_the_changed_digit() {
+        use chrono::TimeZone;
+
+        let start = Local.with_ymd_and_hms(2024, 1, 1, 12, 34, 0).unwrap();
+        let clock = MockClock::new(start.into());
+        let mut screen = mock_screen(62, 128);
+        screen
+            .draw_time(&clock, "%H:%M", 24.0, None, FontWeight::Normal)
+            .unwrap();
+        screen.present().unwrap();
+        assert_eq!(mock(&screen).written().len(), 35);
+        mock(&screen).clear();
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(format_time(&clock, "%H:%M").unwrap(), "12:35");
+        screen.clear();
+        screen
+            .draw_time(&clock, "%H:%M", 24.0, None, FontWeight::Normal)
+            .unwrap();
+        screen.present().unwrap();
+
+        // Only the strips under the last digit change, which fit in a couple of packets
+        // instead of the 35 a full frame takes
+        let written = mock(&screen).written();
+        let bytes: usize = written.iter().map(Vec::len).sum();
+        assert!(
+            (1..=4).contains(&written.len()),
+            "{} packets, {bytes} bytes",
+            written.len()
+        );
+        assert_eq!(bytes, written.len() * PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn aspect_squashes_circles_horizontally() {
+        let mut screen = mock_screen(62, 128);
+        for aspect in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(matches!(
+                screen.set_aspect(aspect),
+                Err(ScreenError::Aspect(_))
+            ));
+        }
+        screen.set_aspect(2.0).unwrap();
+        screen.draw_circle(30, 60, 10, false, true);
+
+        assert_eq!(lit_bounds(&screen), ((25, 35), (50, 70)));
+    }
+
+    #[test]
+    fn spinner_frames_differ_and_cycle() {
+        let spinner = Spinner::new(30, 60, 10.0);
+        let frames: Vec<Vec<u8>> = (0..16)
+            .map(|frame| {
+                let mut screen = mock_screen(62, 128);
+                spinner.draw(&mut screen, frame);
+                assert_eq!(lit_pixels(&screen), 3);
+                screen.data
+            })
+            .collect();
+
+        for frame in 0..8 {
+            assert_ne!(frames[frame], frames[frame + 1]);
+            assert_eq!(frames[frame], frames[frame + 8]);
+        }
+    }
+
+    #[test]
+    fn parse_widgets_accepts_known_names() {
+        assert_eq!(
+            parse_widgets(" clock, cpu,,battery ").unwrap(),
+            [Widget::Clock, Widget::Cpu, Widget::Battery]
+        );
+        assert_eq!(parse_widgets("").unwrap(), []);
+
+        let error = parse_widgets("clock,gpu").unwrap_err();
+        assert!(error.contains("unknown widget `gpu`"), "{error}");
+        assert!(parse_widgets("Clock").is_err());
+    }
+
+    #[test]
+    fn cached_canvas_renders_once_per_key() {
+        let renders = std::cell::Cell::new(0);
+        let render = |screen: &mut Screen| {
+            renders.set(renders.get() + 1);
+            screen.paint_region(10, 10, 15, 15, true);
+        };
+
+        let mut screen = mock_screen(62, 128);
+        let mut cache = CachedCanvas::new(8, 8, 10, 10);
+        assert!(!cache.is_cached());
+        cache.draw(&mut screen, 1, render);
+        assert!(cache.is_cached());
+        let rendered = screen.data.clone();
+
+        // Replayed from the cache onto a blank screen without rendering again
+        screen.clear();
+        cache.draw(&mut screen, 1, render);
+        assert_eq!(renders.get(), 1);
+        assert_eq!(screen.data, rendered);
+
+        cache.draw(&mut screen, 2, render);
+        assert_eq!(renders.get(), 2);
+
+        cache.invalidate();
+        assert!(!cache.is_cached());
+        cache.draw(&mut screen, 2, render);
+        assert_eq!(renders.get(), 3);
+    }
+
+    #[test]
+    fn margins_shift_centered_text_inward() {
+        let draw = |margins: Margins| {
+            let mut screen = mock_screen(62, 128);
+            screen.set_margins(margins);
+            screen
+                .draw_text_aligned("Hi", TextAlign::Center, 10, 16.0, None, FontWeight::Normal)
+                .unwrap();
+            lit_bounds(&screen)
+        };
+
+        let (plain_x, plain_y) = draw(Margins::default());
+        let (inset_x, inset_y) = draw(Margins {
+            top: 20,
+            bottom: 0,
+            left: 6,
+            right: 0,
+        });
+
+        // Centering within 108 of the 128 pixels starting at 20 moves the text 10 along
+        assert_eq!(inset_y, (plain_y.0 + 10, plain_y.1 + 10));
+        assert_eq!(inset_x, (plain_x.0 + 6, plain_x.1 + 6));
+    }
+
+    #[test]
+    fn typewriter_reveals_one_glyph_per_advance() {
+        let mut typewriter = Typewriter::new("Hello");
+        assert_eq!(typewriter.visible(), "");
+
+        let mut previous_end = 0;
+        for count in 1..=5 {
+            typewriter.advance();
+            assert_eq!(typewriter.visible(), &"Hello"[..count]);
+
+            let mut screen = mock_screen(62, 128);
+            typewriter
+                .render(&mut screen, 0, 10, 16.0, None, FontWeight::Normal)
+                .unwrap();
+            // Text runs along the panel's y axis
+            let (_, (_, end)) = lit_bounds(&screen);
+            assert!(end > previous_end, "{count} glyphs end at {end}");
+            previous_end = end;
+        }
+
+        assert!(typewriter.is_done());
+        typewriter.advance();
+        assert_eq!(typewriter.visible(), "Hello");
+    }
+
+    #[test]
+    fn unchanged_frames_build_no_packets() {
+        let mut screen = mock_screen(62, 128);
+        screen.present().unwrap();
+        let after_first = screen.stats();
+        assert_eq!(after_first.packets_built, 35);
+        assert_eq!(after_first.packets_sent, 35);
+
+        screen.present().unwrap();
+        assert_eq!(screen.stats(), after_first);
+
+        screen.set_pixel(0, 0, true);
+        screen.present().unwrap();
+        assert_eq!(screen.stats().packets_built, 70);
+        assert_eq!(screen.stats().packets_sent, 36);
+    }
+
+    #[test]
+    fn bold_text_is_heavier_and_one_pixel_wider_per_glyph() {
+        let draw = |weight: FontWeight| {
+            let mut screen = mock_screen(62, 128);
+            screen
+                .draw_text("Hello", 0, 10, 16.0, None, default_spacing(16.0), weight)
+                .unwrap();
+            (lit_pixels(&screen), lit_bounds(&screen).1)
+        };
+
+        let (normal_lit, (normal_start, normal_end)) = draw(FontWeight::Normal);
+        let (bold_lit, (bold_start, bold_end)) = draw(FontWeight::Bold);
+
+        assert!(bold_lit > normal_lit, "{bold_lit} <= {normal_lit}");
+        assert_eq!(bold_start, normal_start);
+        // Each of the five glyphs may push the next along by at most one pixel
+        assert!(bold_end > normal_end && bold_end <= normal_end + 5);
+    }
+
+    #[test]
+    fn diagonal_and_steep_lines() {
+        let mut screen = mock_screen(62, 128);
+        screen.draw_line(0, 0, 20, 20, true);
+        assert_eq!(lit_pixels(&screen), 21);
+        assert!((0..=20).all(|i| screen.get_pixel(i, i)));
+
+        // A steep line lights exactly one pixel per row, drifting steadily along x
+        let mut screen = mock_screen(62, 128);
+        screen.draw_line(10, 100, 5, 0, true);
+        assert_eq!(lit_pixels(&screen), 101);
+        let columns: Vec<usize> = (0..=100)
+            .map(|y| {
+                let row: Vec<usize> = (0..62).filter(|&x| screen.get_pixel(x, y)).collect();
+                assert_eq!(row.len(), 1, "row {y}");
+                row[0]
+            })
+            .collect();
+        assert_eq!((columns[0], columns[100]), (5, 10));
+        assert!(columns.windows(2).all(|pair| pair[1] - pair[0] <= 1));
+    }
+
+    #[test]
+    fn measure_text_matches_draw_text_pen_movement() {
+        for (size, weight) in [(16.0, FontWeight::Normal), (24.0, FontWeight::Bold)] {
+            let spacing = default_spacing(size);
+            let mut whole = mock_screen(62, 128);
+            whole
+                .draw_text("Hello", 0, 10, size, None, spacing, weight)
+                .unwrap();
+
+            // Drawing the tail where `measure_text` says the head ends reproduces the whole
+            let mut pieces = mock_screen(62, 128);
+            let font = pieces.default_font.clone();
+            let head = pieces.measure_text("Hel", size, &font, spacing + weight.extra_width());
+            pieces
+                .draw_text("Hel", 0, 10, size, None, spacing, weight)
+                .unwrap();
+            pieces
+                .draw_text("lo", head as isize, 10, size, None, spacing, weight)
+                .unwrap();
+
+            assert_eq!(whole.data, pieces.data, "size {size}");
+        }
+    }
+
+    #[test]
+    fn origin_lands_in_each_rotation_corner() {
+        let corners = [
+            (Rotation::Deg0, (0, 0)),
+            (Rotation::Deg90, (61, 0)),
+            (Rotation::Deg180, (61, 127)),
+            (Rotation::Deg270, (0, 127)),
+        ];
+
+        for (rotation, corner) in corners {
+            let mut screen = mock_screen(62, 128);
+            screen.set_rotation(rotation);
+            screen.set_pixel(0, 0, true);
+
+            assert_eq!(lit_pixels(&screen), 1);
+            assert_eq!(
+                lit_bounds(&screen),
+                ((corner.0, corner.0), (corner.1, corner.1))
+            );
+        }
+    }
+
+    #[test]
+    fn mirroring_flips_an_asymmetric_pattern() {
+        // An L, which no flip maps onto itself
+        let pattern = [(2, 3), (2, 4), (2, 5), (3, 5)];
+
+        for (horizontal, vertical) in [(false, false), (true, false), (false, true), (true, true)] {
+            let mut screen = mock_screen(62, 128);
+            screen.set_mirror(horizontal, vertical);
+            for (x, y) in pattern {
+                screen.set_pixel(x, y, true);
+            }
+
+            for (x, y) in pattern {
+                let x = if horizontal { 61 - x } else { x } as usize;
+                let y = if vertical { 127 - y } else { y } as usize;
+                assert!(
+                    screen.raw_pixel(x, y),
+                    "({x}, {y}) for {horizontal}, {vertical}"
+                );
+            }
+            assert_eq!(lit_pixels(&screen), pattern.len());
+        }
+    }
+
+    #[test]
+    fn overly_wide_text_falls_back_to_left_alignment() {
+        let text = "The quick brown fox jumps over the lazy dog";
+
+        for align in [TextAlign::Left, TextAlign::Center, TextAlign::Right] {
+            let mut screen = mock_screen(62, 128);
+            screen
+                .draw_text_aligned(text, align, 0, 64.0, None, FontWeight::Bold)
+                .unwrap();
+
+            // Starts where left aligned text would and is clipped at the far edge
+            let mut left = mock_screen(62, 128);
+            left.draw_text_aligned(text, TextAlign::Left, 0, 64.0, None, FontWeight::Bold)
+                .unwrap();
+            assert_eq!(screen.data, left.data, "{align:?}");
+        }
+
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        let mut screen = mock_screen(62, 128);
+        screen
+            .draw_time(
+                &clock,
+                "%A %d %B %Y %H:%M:%S",
+                64.0,
+                None,
+                FontWeight::Normal,
+            )
+            .unwrap();
+        screen
+            .render_centered(text.repeat(4), 64.0, 20, None, FontWeight::Normal)
+            .unwrap();
+    }
+
+    #[test]
+    fn glyph_footprints_follow_their_metrics() {
+        // NANOTYPE is a pixel font: its 'g' has no descender, so it fills the same box as 'A',
+        // while ',' hangs two pixels past the baseline that '.' and 'A' end on
+        let footprints = [
+            ('.', ((18, 19), (2, 3))),
+            (',', ((18, 21), (2, 3))),
+            ('g', ((10, 19), (0, 5))),
+            ('A', ((10, 19), (0, 5))),
+        ];
+
+        for (letter, footprint) in footprints {
+            let mut screen = mock_screen(62, 128);
+            let font = screen.default_font.clone();
+            screen
+                .draw_text(
+                    &letter.to_string(),
+                    0,
+                    10,
+                    32.0,
+                    None,
+                    0,
+                    FontWeight::Normal,
+                )
+                .unwrap();
+            assert_eq!(lit_bounds(&screen), footprint, "{letter:?}");
+
+            // Glyph rows stack along x from the cap height down to the baseline
+            let metrics = font.metrics(letter, 32.0);
+            let baseline = 10 + cap_height(&font, 32.0);
+            let bottom = baseline - metrics.ymin as isize - 1;
+            let top = bottom + 1 - metrics.height as isize;
+            let (start, end) = (metrics.xmin, metrics.xmin + metrics.width as i32 - 1);
+            assert_eq!(
+                footprint,
+                (
+                    (top as usize, bottom as usize),
+                    (start as usize, end as usize)
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn right_aligned_text_ends_flush_with_the_edge() {
+        for size in [16.0, 32.0, 64.0] {
+            for weight in [FontWeight::Normal, FontWeight::Bold] {
+                let mut screen = mock_screen(62, 128);
+                screen
+                    .draw_text_aligned("Hello", TextAlign::Right, 0, size, None, weight)
+                    .unwrap();
+
+                // Text runs along the 128 pixel y axis
+                let (_, (_, end)) = lit_bounds(&screen);
+                assert_eq!(end, 127, "size {size}, {weight:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn diff_compares_payloads_per_packet_index() {
+        let mut screen = mock_screen(62, 128);
+        screen.set_pixel(0, 0, true);
+        screen.present().unwrap();
+        mock(&screen).clear();
+
+        // One changed byte, one packet
+        screen.set_pixel(9, 100, true);
+        screen.present().unwrap();
+        assert_eq!(mock(&screen).written().len(), 1);
+        mock(&screen).clear();
+
+        // Byte 30 starts packet 1. Moving the pixel there gives packet 1 packet 0's old payload
+        // and packet 0 packet 1's, and both still have to go out.
+        screen.set_pixel(0, 0, false);
+        screen.set_pixel(0, 30, true);
+        screen.present().unwrap();
+        let indices: Vec<u8> = mock(&screen)
+            .written()
+            .iter()
+            .map(|bytes| bytes[1])
+            .collect();
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[test]
+    fn frame_limiter_rejects_invalid_rates() {
+        for fps in [0.0, -30.0, f32::NAN, f32::INFINITY, 1e-20] {
+            assert!(FrameLimiter::from_fps(fps).is_err(), "{fps}");
+        }
+
+        let limiter = FrameLimiter::from_fps(4.0).unwrap();
+        assert_eq!(limiter.interval, Duration::from_millis(250));
+
+        let args = Args::parse_from(["kb", "--fps", "4"]);
+        assert_eq!(args.fps, Some(4.0));
+        assert!(Args::try_parse_from(["kb", "--fps", "4", "--refresh-ms", "100"]).is_err());
+    }
+
+    #[test]
+    fn readouts_get_their_own_lines() {
+        let args = Args::parse_from(["kb"]);
+        let stats = Stats {
+            cpu_usage: 12.5,
+            used_memory: 8_000_000_000,
+            disk: Some((120_000_000_000, 500_000_000_000)),
+            net: (1_200_000.0, 300_000.0),
+            cpu_temp: Some(45.0),
+            ..Stats::default()
+        };
+        let widgets = [
+            Widget::Cpu,
+            Widget::Mem,
+            Widget::Disk,
+            Widget::Net,
+            Widget::Temp,
+        ];
+
+        let mut screen = mock_screen(62, 128);
+        draw_frame(&mut screen, &args, None, &widgets, &stats).unwrap();
+
+        // Nothing runs off either end of a line
+        assert!((0..62).all(|x| !screen.get_pixel(x, 0) && !screen.get_pixel(x, 127)));
+
+        // Lines stack across x, so each readout is its own run of lit rows
+        let lit_rows: Vec<bool> = (0..62)
+            .map(|x| (0..128).any(|y| screen.get_pixel(x, y)))
+            .collect();
+        let runs = lit_rows
+            .iter()
+            .zip(std::iter::once(&false).chain(&lit_rows))
+            .filter(|&(&lit, &before)| lit && !before)
+            .count();
+        assert_eq!(runs, widgets.len());
+    }
+
+    #[test]
+    fn short_writes_reach_the_caller() {
+        let mut screen = mock_screen(62, 128);
+        mock(&screen).set_short_writes(true);
+
+        match screen.present() {
+            Err(ScreenError::Disconnected(HidError::IncompleteSendError { sent, all })) => {
+                assert_eq!((sent, all), (PAYLOAD_SIZE - 1, PAYLOAD_SIZE));
+            }
+            result => panic!("expected an incomplete send, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn widget_list_overrides_the_layout() {
+        let layout =
+            || -> Layout { toml::from_str("[[element]]\ntype = \"disk\"\ny = 10\n").unwrap() };
+
+        let (kept, widgets) = select_widgets(Some(layout()), None).unwrap();
+        assert!(kept.is_some());
+        assert_eq!(widgets, [Widget::Disk]);
+
+        let (kept, widgets) = select_widgets(Some(layout()), Some("cpu,mem")).unwrap();
+        assert!(kept.is_none());
+        assert_eq!(widgets, [Widget::Cpu, Widget::Mem]);
+
+        assert!(select_widgets(Some(layout()), Some("gpu")).is_err());
+
+        let (_, widgets) = select_widgets(None, None).unwrap();
+        assert_eq!(widgets, Widget::DEFAULT);
+    }
+
+    #[test]
+    fn exports_round_trip_a_known_pattern() {
+        let mut screen = mock_screen(62, 128);
+        let lit = |x: usize, y: usize| (x * 3 + y * 7) % 5 == 1 || x == 61;
+        for (x, y) in (0..62).cartesian_product(0..128) {
+            screen.set_pixel(x as isize, y as isize, lit(x, y));
+        }
+
+        let pbm = screen.to_pbm();
+        let mut lines = pbm.lines();
+        assert_eq!(lines.next(), Some("P1"));
+        assert_eq!(lines.next(), Some("62 128"));
+        let pixels: Vec<bool> = lines.flat_map(str::chars).map(|c| c == '1').collect();
+        assert_eq!(pixels.len(), 62 * 128);
+        for (index, &pixel) in pixels.iter().enumerate() {
+            assert_eq!(pixel, lit(index % 62, index / 62), "PBM pixel {index}");
+        }
+
+        let path = env::temp_dir().join(format!("kb-test-{}.xbm", std::process::id()));
+        screen.save_xbm(path.to_str().unwrap()).unwrap();
+        let xbm = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(xbm.starts_with("#define kb_width 62\n#define kb_height 128\n"));
+        let bytes: Vec<u8> = xbm
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|token| token.strip_prefix("0x"))
+            .map(|hex| u8::from_str_radix(hex, 16).unwrap())
+            .collect();
+        assert_eq!(bytes.len(), 8 * 128);
+        for (x, y) in (0..62).cartesian_product(0..128) {
+            let byte = bytes[y * 8 + x / 8];
+            assert_eq!(byte & 1 << (x % 8) != 0, lit(x, y), "XBM pixel ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn send_policy_retries_failed_writes() {
+        let clock = Rc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        let mut screen = mock_screen(62, 128);
+        screen.set_clock(clock.clone());
+        screen.set_send_policy(SendPolicy {
+            retries: 2,
+            delay: Duration::from_millis(10),
+        });
+
+        mock(&screen).fail_next(2);
+        screen.present().unwrap();
+        assert_eq!(mock(&screen).written().len(), 35);
+        assert_eq!(
+            clock.now(),
+            SystemTime::UNIX_EPOCH + Duration::from_millis(20)
+        );
+
+        // One failure more than the policy allows loses the frame
+        screen.force_full_redraw();
+        mock(&screen).fail_next(3);
+        assert!(matches!(
+            screen.present(),
+            Err(ScreenError::Disconnected(_))
+        ));
+    }
+
+    #[test]
+    fn to_packets_splits_the_buffer_into_chunks() {
+        let chunk_size = Protocol::default().chunk_size();
+
+        // Exact multiples of the chunk size need no partial packet
+        for strips in 1..=3 {
+            let screen = mock_screen(8 * strips, chunk_size);
+            assert_eq!(screen.to_packets().len(), strips);
+        }
+
+        // 8 strips of 128 bytes is 34 full packets and 4 bytes over
+        let mut screen = mock_screen(62, 128);
+        screen.fill_all();
+        let packets = screen.to_packets();
+        assert_eq!(packets.len(), 35);
+
+        for (index, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.index as usize, index);
+            assert_eq!(packet.payload.len(), chunk_size);
+        }
+
+        // Four real bytes, then zeros rather than anything past the end of the buffer
+        let last = &packets[34].payload;
+        assert_eq!(last[..4], [0xFF; 4]);
+        assert!(last[4..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn packet_count_is_limited_to_an_index_byte() {
+        let chunk_size = Protocol::default().chunk_size();
+
+        assert!(Screen::from_device(MockHidAdapter::new(), 8, MAX_PACKETS * chunk_size).is_ok());
+        assert!(matches!(
+            Screen::from_device(MockHidAdapter::new(), 8, MAX_PACKETS * chunk_size + 1),
+            Err(ScreenError::TooManyPackets { .. })
+        ));
+        for (width, height) in [(0, 128), (62, 0)] {
+            assert!(matches!(
+                Screen::from_device(MockHidAdapter::new(), width, height),
+                Err(ScreenError::EmptyScreen { width: w, height: h }) if (w, h) == (width, height)
+            ));
+        }
+
+        // Shrinking the packets can push an otherwise valid screen over the limit
+        let mut screen = mock_screen(8, MAX_PACKETS * chunk_size);
+        let smaller = Protocol {
+            payload_size: PAYLOAD_SIZE - 1,
+            ..Protocol::default()
+        };
+        let packets = buffer_len(8, MAX_PACKETS * chunk_size).div_ceil(smaller.chunk_size());
+        assert!(matches!(
+            screen.set_protocol(smaller),
+            Err(ScreenError::TooManyPackets { packets: p, max: MAX_PACKETS }) if p == packets
+        ));
+        assert_eq!(screen.protocol, Protocol::default());
+
+        let tiny = Protocol {
+            payload_size: 2,
+            ..Protocol::default()
+        };
+        assert!(matches!(
+            screen.set_protocol(tiny),
+            Err(ScreenError::PayloadSize(2))
+        ));
+    }
+
+    #[test]
+    fn text_bounds_cover_every_line() {
+        let mut screen = mock_screen(62, 128);
+        let font = screen.default_font.clone();
+
+        let (short_width, line) = screen.text_bounds("Hi", 16.0, &font);
+        let (long_width, _) = screen.text_bounds("there", 16.0, &font);
+        let pitch = screen.line_height(&font, 16.0) as usize;
+
+        // As wide as the widest line, one line pitch taller per extra line
+        assert_eq!(
+            screen.text_bounds("Hi\nthere", 16.0, &font),
+            (long_width.max(short_width), pitch + line)
+        );
+        assert_eq!(
+            screen.text_bounds("Hi\nthere\nHi", 16.0, &font),
+            (long_width.max(short_width), 2 * pitch + line)
+        );
+
+        screen.set_line_spacing(3);
+        assert_eq!(
+            screen.text_bounds("Hi\nthere", 16.0, &font).1,
+            pitch + 3 + line
+        );
+    }
+
+    #[test]
+    fn layouts_that_dont_fit_fall_back_to_the_default() {
+        let screen = mock_screen(62, 128);
+        let layout = |y: isize| -> Layout {
+            toml::from_str(&format!("[[element]]\ntype = \"disk\"\ny = {y}\n")).unwrap()
+        };
+
+        let (kept, widgets) = check_layout(Some(layout(10)), vec![Widget::Disk], &screen);
+        assert!(kept.is_some());
+        assert_eq!(widgets, [Widget::Disk]);
+
+        // Lines stack across the 62 pixel width
+        let (kept, widgets) = check_layout(Some(layout(100)), vec![Widget::Disk], &screen);
+        assert!(kept.is_none());
+        assert_eq!(widgets, Widget::DEFAULT);
+    }
+
+    #[test]
+    fn from_packets_inverts_to_packets() {
+        let mut screen = mock_screen(62, 128);
+        for (x, y) in (0..62).cartesian_product(0..128) {
+            screen.set_pixel(x, y, (x * 5 + y * 3) % 7 == 1);
+        }
+
+        let mut packets = screen.to_packets();
+        assert_eq!(Screen::from_packets(&packets, 62, 128), screen.data);
+
+        packets.reverse();
+        assert_eq!(Screen::from_packets(&packets, 62, 128), screen.data);
+
+        // What actually went over the wire reassembles into the same frame
+        screen.present().unwrap();
+        let sent: Vec<DataPacket> = mock(&screen)
+            .written()
+            .iter()
+            .map(|bytes| DataPacket::new(bytes[1], bytes[2..].to_vec()))
+            .collect();
+        assert_eq!(Screen::from_packets(&sent, 62, 128), screen.data);
+
+        assert!(Screen::from_packets(&[], 62, 128)
+            .iter()
+            .all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn inverted_output_complements_transmitted_bytes() {
+        let mut screen = mock_screen(62, 128);
+        screen.set_pixel(3, 5, true);
+        screen.present().unwrap();
+        mock(&screen).clear();
+
+        // Toggling inversion resends the whole frame
+        screen.set_invert_output(true);
+        screen.present().unwrap();
+        let sent: Vec<DataPacket> = mock(&screen)
+            .written()
+            .iter()
+            .map(|bytes| DataPacket::new(bytes[1], bytes[2..].to_vec()))
+            .collect();
+        assert_eq!(sent.len(), 35);
+
+        let complemented: Vec<u8> = screen.data.iter().map(|byte| !byte).collect();
+        assert_eq!(Screen::from_packets(&sent, 62, 128), complemented);
+
+        // Drawing still sees the uninverted image
+        assert!(screen.get_pixel(3, 5));
+        assert!(!screen.get_pixel(4, 5));
+    }
+
+    #[test]
+    fn fill_all_lights_every_pixel() {
+        for rotation in [Rotation::Deg0, Rotation::Deg90] {
+            let mut screen = mock_screen(62, 128);
+            screen.set_rotation(rotation);
+            screen.set_margins(Margins {
+                top: 3,
+                bottom: 2,
+                left: 1,
+                right: 4,
+            });
+            screen.fill_all();
+
+            // Margins only narrow what drawing reaches, fill_all covers the whole panel
+            assert!((0..62)
+                .cartesian_product(0..128)
+                .all(|(x, y)| screen.raw_pixel(x, y)));
+            assert!((0..screen.inset_width())
+                .cartesian_product(0..screen.inset_height())
+                .all(|(x, y)| screen.get_pixel(x, y)));
+        }
+    }
 }
@@ -0,0 +1,65 @@
+//! Helpers for exercising `Screen` without a keyboard attached.
+
+use std::cell::{Cell, Ref, RefCell};
+
+use hidapi::HidError;
+
+use crate::HidAdapter;
+
+/// A `HidAdapter` that records the bytes of every write, so the packets a `Screen` sends can be
+/// inspected afterwards via `Screen::device().as_any()`. Writes succeed unless told to fail
+/// (`fail_next`) or to come up short (`set_short_writes`).
+#[derive(Default)]
+pub struct MockHidAdapter {
+    written: RefCell<Vec<Vec<u8>>>,
+    failures: Cell<usize>,
+    short_writes: Cell<bool>,
+}
+
+impl MockHidAdapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every successful write so far, oldest first
+    pub fn written(&self) -> Ref<'_, [Vec<u8>]> {
+        Ref::map(self.written.borrow(), |written| written.as_slice())
+    }
+
+    pub fn clear(&self) {
+        self.written.borrow_mut().clear();
+    }
+
+    /// Makes the next `count` writes fail without recording anything
+    pub fn fail_next(&self, count: usize) {
+        self.failures.set(count);
+    }
+
+    /// While enabled, writes are recorded but report one byte fewer than they were given
+    pub fn set_short_writes(&self, enabled: bool) {
+        self.short_writes.set(enabled);
+    }
+}
+
+impl HidAdapter for MockHidAdapter {
+    fn write(&self, data: &[u8]) -> Result<usize, HidError> {
+        if self.failures.get() > 0 {
+            self.failures.set(self.failures.get() - 1);
+            return Err(HidError::HidApiError {
+                message: "mock write failure".to_string(),
+            });
+        }
+
+        self.written.borrow_mut().push(data.to_vec());
+
+        if self.short_writes.get() {
+            Ok(data.len().saturating_sub(1))
+        } else {
+            Ok(data.len())
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}